@@ -0,0 +1,163 @@
+use std::cmp::Reverse;
+
+use fancy_regex::Regex;
+
+use crate::string_operations::matches_path_prefix;
+
+pub(crate) const DEFAULT_REALM: &str = "User Visible Realm";
+
+/// A segment-aware path prefix, or a glob where "*" and "?" stay within a segment and "**" spans segments
+#[derive(Debug, Clone)]
+pub enum PathPattern {
+    Prefix(String),
+    Glob(String, Regex),
+}
+
+impl PathPattern {
+    pub(crate) fn parse(pattern: &str) -> Result<PathPattern, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("Path pattern {pattern} has to start with /"));
+        }
+        if !pattern.contains(['*', '?']) {
+            return Ok(PathPattern::Prefix(pattern.to_string()));
+        }
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    regex += ".*";
+                }
+                '*' => regex += "[^/]*",
+                '?' => regex += "[^/]",
+                _ => regex += fancy_regex::escape(&c.to_string()).as_ref()
+            }
+        }
+        regex += "$";
+        Regex::new(&regex)
+            .map(|compiled| PathPattern::Glob(pattern.to_string(), compiled))
+            .map_err(|e| format!("Invalid path pattern {pattern}: {e}"))
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        match self {
+            PathPattern::Prefix(prefix) => matches_path_prefix(path, prefix),
+            PathPattern::Glob(_, regex) => regex.is_match(path).unwrap_or(false),
+        }
+    }
+
+    /// Patterns with more literal characters are more specific; on a tie a glob is narrower than a prefix
+    fn specificity(&self) -> (usize, bool) {
+        match self {
+            PathPattern::Prefix(prefix) => (prefix.trim_end_matches('/').len(), false),
+            PathPattern::Glob(glob, _) => (glob.chars().filter(|c| *c != '*' && *c != '?').count(), true),
+        }
+    }
+}
+
+/// Who may access the paths matching the pattern. Without users and groups any valid user is accepted,
+/// without methods any method is.
+#[derive(Debug, Clone)]
+pub struct AclRule {
+    pub pattern: PathPattern,
+    pub realm: String,
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+    pub methods: Vec<String>,
+    pub public: bool,
+}
+
+impl AclRule {
+    pub(crate) fn new(pattern: PathPattern) -> AclRule {
+        AclRule {
+            pattern,
+            realm: DEFAULT_REALM.to_string(),
+            users: vec![],
+            groups: vec![],
+            methods: vec![],
+            public: false,
+        }
+    }
+
+    pub(crate) fn allows_method(&self, method: &str) -> bool {
+        self.methods.is_empty() || self.methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+}
+
+/// Parses a rule like "/reports/**/*.pdf;realm=Reports;users=alice,bob;groups=finance;methods=GET,HEAD".
+/// A rule with "public" needs no authentication, which opens up a path inside a protected folder.
+pub fn parse_acl_rule(rule: &str) -> Result<AclRule, String> {
+    let mut parts = rule.split(';').map(|part| part.trim());
+    let mut acl_rule = AclRule::new(PathPattern::parse(parts.next().unwrap_or(""))?);
+    for part in parts.filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        let values = || value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "realm" if !value.is_empty() => acl_rule.realm = value.to_string(),
+            "users" => acl_rule.users = values(),
+            "groups" => acl_rule.groups = values(),
+            "methods" => acl_rule.methods = values().iter().map(|method| method.to_ascii_uppercase()).collect(),
+            "public" => acl_rule.public = true,
+            _ => return Err(format!("Unknown ACL setting {part}"))
+        }
+    }
+    Ok(acl_rule)
+}
+
+/// The most specific rule matching the path, on a tie the first one, so ACL rules win over protected folders
+pub(crate) fn find_acl_rule<'a>(path: &str, rules: impl IntoIterator<Item=&'a AclRule>) -> Option<&'a AclRule> {
+    rules.into_iter()
+        .filter(|rule| rule.pattern.matches(path))
+        // min_by_key keeps the first of equally specific rules, where max_by_key would keep the last
+        .min_by_key(|rule| Reverse(rule.pattern.specificity()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_parse_acl_rule_should_read_all_settings() {
+        let rule = parse_acl_rule("/reports;realm=Reports;users=alice,bob;groups=finance;methods=get,head").unwrap();
+        assert_eq!(rule.realm, "Reports");
+        assert_eq!(rule.users, vec!["alice", "bob"]);
+        assert_eq!(rule.groups, vec!["finance"]);
+        assert!(rule.allows_method("GET"));
+        assert!(!rule.allows_method("DELETE"));
+        assert!(!rule.public);
+        assert!(parse_acl_rule("/reports/public;public").unwrap().public);
+        assert!(parse_acl_rule("reports").is_err());
+        assert!(parse_acl_rule("/reports;owner=alice").is_err());
+    }
+
+    #[test]
+    fn when_path_pattern_should_match_segments() {
+        let prefix = PathPattern::parse("/api").unwrap();
+        assert!(prefix.matches("/api"));
+        assert!(prefix.matches("/api/users"));
+        assert!(!prefix.matches("/apiary"));
+
+        let glob = PathPattern::parse("/reports/*.pdf").unwrap();
+        assert!(glob.matches("/reports/q1.pdf"));
+        assert!(!glob.matches("/reports/2024/q1.pdf"));
+        let deep_glob = PathPattern::parse("/reports/**/q?.pdf").unwrap();
+        assert!(deep_glob.matches("/reports/2024/eu/q1.pdf"));
+        assert!(!deep_glob.matches("/reports/2024/q10.pdf"));
+    }
+
+    #[test]
+    fn when_find_acl_rule_should_prefer_most_specific() {
+        let rules = vec![
+            parse_acl_rule("/;realm=Everything").unwrap(),
+            parse_acl_rule("/reports;realm=Reports").unwrap(),
+            parse_acl_rule("/reports/*.pdf;realm=Pdf").unwrap(),
+            parse_acl_rule("/reports/public;public").unwrap(),
+        ];
+        assert_eq!(find_acl_rule("/index.html", &rules).unwrap().realm, "Everything");
+        assert_eq!(find_acl_rule("/reports/q1.csv", &rules).unwrap().realm, "Reports");
+        assert_eq!(find_acl_rule("/reports/q1.pdf", &rules).unwrap().realm, "Pdf");
+        assert!(find_acl_rule("/reports/public/a.pdf", &rules).unwrap().public);
+        assert_eq!(find_acl_rule("/reportsx", &rules).unwrap().realm, "Everything");
+    }
+}
@@ -1,9 +1,24 @@
 use clap:: {
     Args,
     Parser,
-    Subcommand
+    Subcommand,
+    ValueEnum
 };
 
+use std::net::IpAddr;
+
+use rustls::SupportedCipherSuite;
+
+use crate::acl::{AclRule, parse_acl_rule};
+use crate::cors::{CorsRule, parse_cors_rule};
+use crate::header_parser::parse_response_header;
+use crate::ip_access::{IpRule, parse_ip_rule};
+use crate::ip_network::{IpNetwork, parse_ip_network};
+use crate::listener::{Listen, parse_listen};
+use crate::mime_type_map::{MimeTypeOverride, parse_mime_type_override};
+use crate::tls::{parse_cipher_suite, parse_tls_certificate, TlsCertificate};
+use crate::virtual_host::parse_server_name;
+
 /// Simple Http Server
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -16,22 +31,86 @@ pub struct HttpServerArgs {
 #[derive(Debug, Subcommand)]
 pub enum Mode {
     /// Run the server
-    Run(RunCommand),
+    Run(Box<RunCommand>),
 
     /// Print info about the server
     Info(InfoCommand),
+
+    /// Print a signed URL which grants access to one path until it expires
+    Sign(SignCommand),
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct RunCommand {
 
-    /// The server port
-    #[clap(short, long)]
-    pub port: u16,
+    /// TOML or YAML file with the settings of this command, e.g. server.toml. Its keys are the long option names,
+    /// "${NAME}" and "${NAME:-default}" in values are replaced with environment variables. Options given on the
+    /// command line replace the values of the file.
+    #[clap(long)]
+    pub config: Option<String>,
+
+    /// The port of the plain HTTP listener, can be left out when the TLS port or another listener is set
+    #[clap(short, long, required_unless_present_any = ["tls_port", "listen"], requires = "host")]
+    pub port: Option<u16>,
+
+    /// The server host, the address of the plain and the TLS port
+    #[clap(long, required_unless_present = "listen")]
+    pub host: Option<String>,
+
+    /// Further address to listen on, e.g. "[::]:80", "[::]:443;tls" or "unix:/run/http_server.sock;mode=660".
+    /// "[::]" accepts IPv4 clients as well unless followed by ";ipv6-only", ";tls" serves HTTPS with the certificates
+    /// of --tls-cert. Can be repeated
+    #[clap(long, value_parser = parse_listen)]
+    pub listen: Vec<Listen>,
+
+    /// Host names of the default host, e.g. example.com,*.example.com. Requests for names of no virtual host
+    /// are served by it, see --unknown-host
+    #[clap(long, value_parser = parse_server_name, value_delimiter = ',')]
+    pub server_names: Vec<String>,
 
-    /// The server host
+    /// How requests for a host name of no virtual host and not of the server names are answered
+    #[clap(long, value_enum, default_value_t = UnknownHost::Default)]
+    pub unknown_host: UnknownHost,
+
+    /// The port of the HTTPS listener
+    #[clap(long, requires_all = ["tls_certs", "host"])]
+    pub tls_port: Option<u16>,
+
+    /// Certificate chain and private key PEM files, e.g. "certs/example.com.pem,certs/example.com.key".
+    /// Can be repeated, the certificate valid for the SNI server name is chosen, otherwise the first one.
+    /// Replaced files are reloaded without a restart.
+    #[clap(long = "tls-cert", value_parser = parse_tls_certificate)]
+    pub tls_certs: Vec<TlsCertificate>,
+
+    /// Accepted TLS protocol versions
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = [TlsVersion::V13, TlsVersion::V12])]
+    pub tls_versions: Vec<TlsVersion>,
+
+    /// Cipher suites in the order of preference of the server, e.g. TLS13_AES_256_GCM_SHA384,TLS13_AES_128_GCM_SHA256.
+    /// All suites of the accepted versions when not set
+    #[clap(long, value_parser = parse_cipher_suite, value_delimiter = ',')]
+    pub tls_cipher_suites: Vec<SupportedCipherSuite>,
+
+    /// Answer every request on the plain TCP listeners with a permanent redirect to the same URL on the TLS port,
+    /// or else on the port of the first TLS listener. Unix domain sockets are served, as a proxy in front terminates TLS
     #[clap(long)]
-    pub host: String,
+    pub https_redirect: bool,
+
+    /// Seconds browsers keep to HTTPS after a response over TLS, sent as Strict-Transport-Security. Not sent when not set
+    #[clap(long)]
+    pub hsts_max_age: Option<u64>,
+
+    /// Extend the Strict-Transport-Security policy to all subdomains
+    #[clap(long, requires = "hsts_max_age")]
+    pub hsts_include_subdomains: bool,
+
+    /// Ask for inclusion in the HSTS preload lists of the browsers, which also needs the subdomains
+    #[clap(long, requires_all = ["hsts_max_age", "hsts_include_subdomains"])]
+    pub hsts_preload: bool,
+
+    /// Application protocols offered through ALPN, in order of preference
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = [AlpnProtocol::Http11, AlpnProtocol::Http10])]
+    pub alpn: Vec<AlpnProtocol>,
 
     // The size of the thread pool
     #[clap(long, default_value_t = 4)]
@@ -41,6 +120,100 @@ pub struct RunCommand {
     #[clap(long, default_value_t = String::from("root"))]
     pub root_folder: String,
 
+    /// Files served for a folder instead of its listing, the first one found wins
+    #[clap(long, value_delimiter = ',', default_values_t = [String::from("index.html"), String::from("index.htm")])]
+    pub index: Vec<String>,
+
+    /// Folder with the pages of error responses like not_found.html, the root folder when not set
+    #[clap(long)]
+    pub error_pages: Option<String>,
+
+    /// Seconds an idle persistent connection is kept open, also the time in which a request head has to arrive
+    #[clap(long, default_value_t = 5)]
+    pub keep_alive_timeout: u64,
+
+    /// Seconds a response may wait for the client to read it before the connection is closed
+    #[clap(long, default_value_t = 30)]
+    pub write_timeout: u64,
+
+    /// The maximum number of requests served on one connection
+    #[clap(long, default_value_t = 100)]
+    pub max_requests: usize,
+
+    /// The maximum size of a request body in bytes
+    #[clap(long, default_value_t = 10 * 1024 * 1024)]
+    pub max_body_size: usize,
+
+    /// Charset announced for text files which are not UTF-8, e.g. iso-8859-1. Omitted when not set
+    #[clap(long)]
+    pub default_charset: Option<String>,
+
+    /// How symbolic links inside the root folder are handled
+    #[clap(long, value_enum, default_value_t = SymlinkPolicy::FollowWithinRoot)]
+    pub symlinks: SymlinkPolicy,
+
+    /// How entity tags are generated for files
+    #[clap(long, value_enum, default_value_t = EtagMode::Strong)]
+    pub etag: EtagMode,
+
+    /// Content codings offered for compressible responses, in order of preference, and for which precompressed sidecar files are served
+    #[clap(long, value_enum, value_delimiter = ',',
+    default_values_t = [ContentCoding::Br, ContentCoding::Zstd, ContentCoding::Gzip, ContentCoding::Deflate])]
+    pub compression: Vec<ContentCoding>,
+
+    /// Send all responses uncompressed, including precompressed .br, .zst and .gz sidecar files
+    #[clap(long)]
+    pub no_compression: bool,
+
+    /// Files smaller than this number of bytes are sent uncompressed
+    #[clap(long, default_value_t = 1024)]
+    pub compression_min_size: u64,
+
+    /// CORS policy for a path prefix, e.g. "/api;origins=https://*.example.com;methods=GET,HEAD;
+    /// headers=Authorization;expose=ETag;credentials;max-age=600". Can be repeated, the longest matching prefix wins.
+    #[clap(long, value_parser = parse_cors_rule)]
+    pub cors: Vec<CorsRule>,
+
+    /// Header added to every response, e.g. "X-Frame-Options: DENY". Can be repeated
+    #[clap(long = "header", value_parser = parse_response_header)]
+    pub headers: Vec<String>,
+
+    /// Content type of a file extension replacing the built-in one, e.g. "md=text/markdown". Types other than text/*,
+    /// +xml and +json count as binary unless followed by ";text", ";attachment" makes browsers download the file.
+    /// Can be repeated
+    #[clap(long = "mime-type", value_parser = parse_mime_type_override)]
+    pub mime_types: Vec<MimeTypeOverride>,
+
+    /// Address rule for a path prefix, e.g. "/intranet;deny=10.9.0.0/16;allow=10.0.0.0/8,2001:db8::/32;satisfy=any".
+    /// The first matching entry decides, the default is deny. satisfy=all needs an allowed address and valid credentials,
+    /// satisfy=any either of them. Can be repeated, the longest matching prefix wins.
+    #[clap(long = "ip-rule", value_parser = parse_ip_rule)]
+    pub ip_rules: Vec<IpRule>,
+
+    /// Failed authentications after which the client address and the user are locked out. 0 disables the lockout.
+    #[clap(long, default_value_t = 5)]
+    pub max_auth_failures: u32,
+
+    /// Seconds of the first lockout, every further lockout lasts twice as long
+    #[clap(long, default_value_t = 60)]
+    pub lockout_seconds: u64,
+
+    /// Upper limit for the lockout in seconds
+    #[clap(long, default_value_t = 3600)]
+    pub max_lockout_seconds: u64,
+
+    /// Networks which are never locked out, e.g. 10.0.0.0/8,2001:db8::/32
+    #[clap(long, value_parser = parse_ip_network, value_delimiter = ',')]
+    pub trusted_networks: Vec<IpNetwork>,
+
+    /// File to which failed authentications and lockouts are appended, besides the standard output
+    #[clap(long)]
+    pub audit_log: Option<String>,
+
+    /// File with the secret of signed URLs, which grant access without credentials. See the sign command.
+    #[clap(long)]
+    pub url_secret: Option<String>,
+
     #[clap(subcommand)]
     pub auth_mode: AuthMode
 }
@@ -49,12 +222,109 @@ pub struct RunCommand {
 pub struct InfoCommand {
 }
 
+#[derive(Debug, Args)]
+pub struct SignCommand {
+
+    /// The path to grant access to, e.g. /reports/q1.pdf
+    #[clap(long)]
+    pub path: String,
+
+    /// File with the secret the server was started with
+    #[clap(long)]
+    pub url_secret: String,
+
+    /// Seconds until the URL expires
+    #[clap(long, default_value_t = 3600)]
+    pub expires_in: u64,
+
+    /// Only accept the URL from this client address
+    #[clap(long)]
+    pub client_ip: Option<IpAddr>,
+
+    /// Prefix for the printed URL, e.g. https://files.example.com
+    #[clap(long, default_value_t = String::new())]
+    pub base_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Follow all symbolic links, even when they point outside the root folder
+    Follow,
+    /// Follow symbolic links as long as their target is inside the root folder
+    FollowWithinRoot,
+    /// Refuse any path containing a symbolic link
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UnknownHost {
+    /// Serve the request from the default host
+    Default,
+    /// Answer with 421 Misdirected Request
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContentCoding {
+    /// Brotli
+    Br,
+    /// Zstandard
+    Zstd,
+    /// Gzip
+    Gzip,
+    /// Zlib stream, which is what HTTP calls deflate
+    Deflate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    V12,
+    #[value(name = "1.3")]
+    V13,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AlpnProtocol {
+    #[value(name = "http/1.1")]
+    Http11,
+    #[value(name = "http/1.0")]
+    Http10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DigestAlgorithm {
+    /// SHA-256, preferred by current clients
+    #[value(name = "sha-256")]
+    Sha256,
+    /// MD5, for older clients
+    Md5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EtagMode {
+    /// Strong entity tag derived from size and modification time
+    Strong,
+    /// Weak entity tag derived from size and modification time
+    Weak,
+    /// Strong entity tag derived from a SHA-256 hash of the content
+    Hash,
+    /// No entity tags
+    None,
+}
+
 #[derive(Debug, Subcommand, Clone)]
 pub enum AuthMode {
     /// No authentication
     None(NoneAuthCommand),
     /// Just basic authentication
-    Basic(BasicAuthCommand)
+    Basic(BasicAuthCommand),
+    /// Digest authentication, which never sends the password
+    Digest(DigestAuthCommand),
+    /// Bearer authentication with API tokens or JSON Web Tokens
+    Bearer(BearerAuthCommand),
+    /// Client certificates verified during the TLS handshake
+    Certificate(CertificateAuthCommand)
 }
 
 #[derive(Debug, Args, Clone)]
@@ -68,6 +338,60 @@ pub struct BasicAuthCommand {
     #[clap(long, default_value_t = String::from("root"))]
     pub protected_folders: String,
 
+    /// Apache-style htpasswd file with one user per line, used instead of the username and password.
+    /// Changes to the file are picked up without a restart.
+    #[clap(long)]
+    pub htpasswd: Option<String>,
+
+    /// Apache-style htgroup file with lines like "finance: alice bob", used by the groups of the ACL rules
+    #[clap(long)]
+    pub htgroup: Option<String>,
+
+    /// Access rule for a path prefix or glob, e.g. "/reports/**/*.pdf;realm=Reports;users=alice;groups=finance;methods=GET,HEAD".
+    /// "public" opens a path up. Can be repeated, the most specific rule wins over the protected folders.
+    #[clap(long, value_parser = parse_acl_rule)]
+    pub acl: Vec<AclRule>,
+
+    /// The user name
+    #[clap(long, default_value_t = String::from("admin"))]
+    pub username: String,
+
+    /// The password
+    #[clap(long, default_value_t = String::from("password"))]
+    pub password: String,
+}
+
+
+
+#[derive(Debug, Args, Clone)]
+pub struct DigestAuthCommand {
+
+    /// The protected folders
+    #[clap(long, default_value_t = String::from("root"))]
+    pub protected_folders: String,
+
+    /// Apache-style htdigest file with "user:realm:ha1" lines, used instead of the username and password.
+    /// A user can have an MD5 and a SHA-256 line. Changes to the file are picked up without a restart.
+    #[clap(long)]
+    pub htdigest: Option<String>,
+
+    /// Apache-style htgroup file with lines like "finance: alice bob", used by the groups of the ACL rules
+    #[clap(long)]
+    pub htgroup: Option<String>,
+
+    /// Access rule for a path prefix or glob, e.g. "/reports/**/*.pdf;realm=Reports;users=alice;groups=finance;methods=GET,HEAD".
+    /// "public" opens a path up. Can be repeated, the most specific rule wins over the protected folders.
+    #[clap(long, value_parser = parse_acl_rule)]
+    pub acl: Vec<AclRule>,
+
+    /// Digest algorithms offered to clients, in order of preference
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = [DigestAlgorithm::Sha256, DigestAlgorithm::Md5])]
+    pub algorithms: Vec<DigestAlgorithm>,
+
+    /// Seconds a server nonce stays valid before clients have to repeat the request with a fresh one
+    #[clap(long, default_value_t = 300)]
+    pub nonce_lifetime: u64,
+
     /// The user name
     #[clap(long, default_value_t = String::from("admin"))]
     pub username: String,
@@ -77,4 +401,109 @@ pub struct BasicAuthCommand {
     pub password: String,
 }
 
+#[derive(Debug, Args, Clone)]
+pub struct BearerAuthCommand {
 
+    /// The protected folders
+    #[clap(long, default_value_t = String::from("root"))]
+    pub protected_folders: String,
+
+    /// File with one "token username group1 group2" line per API token. Changes to the file are picked up without a restart.
+    #[clap(long)]
+    pub tokens: Option<String>,
+
+    /// File with the shared secret of HS256 signed JSON Web Tokens
+    #[clap(long)]
+    pub jwt_secret: Option<String>,
+
+    /// PEM file with the RSA or P-256 public key of RS256 or ES256 signed JSON Web Tokens
+    #[clap(long)]
+    pub jwt_public_key: Option<String>,
+
+    /// JSON Web Key Set file with the keys of signed JSON Web Tokens, selected by their key id
+    #[clap(long)]
+    pub jwks: Option<String>,
+
+    /// The audience JSON Web Tokens have to be issued for
+    #[clap(long)]
+    pub audience: Option<String>,
+
+    /// The issuer JSON Web Tokens have to come from
+    #[clap(long)]
+    pub issuer: Option<String>,
+
+    /// The claim holding the user name
+    #[clap(long, default_value_t = String::from("sub"))]
+    pub username_claim: String,
+
+    /// The claim holding the groups, either an array or a space separated string
+    #[clap(long, default_value_t = String::from("groups"))]
+    pub groups_claim: String,
+
+    /// Seconds of clock skew tolerated when checking exp and nbf
+    #[clap(long, default_value_t = 60)]
+    pub leeway: u64,
+
+    /// Apache-style htgroup file with lines like "finance: alice bob", used by the groups of the ACL rules
+    #[clap(long)]
+    pub htgroup: Option<String>,
+
+    /// Access rule for a path prefix or glob, e.g. "/reports/**/*.pdf;realm=Reports;users=alice;groups=finance;methods=GET,HEAD".
+    /// "public" opens a path up. Can be repeated, the most specific rule wins over the protected folders.
+    #[clap(long, value_parser = parse_acl_rule)]
+    pub acl: Vec<AclRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClientAuth {
+    /// Every TLS client has to present a valid certificate
+    Required,
+    /// Clients are asked for a certificate, which only the protected folders demand
+    Optional,
+}
+
+/// The part of the client certificate which names the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CertificateIdentity {
+    /// The common name of the subject
+    Cn,
+    /// The first e-mail address of the subject alternative names
+    Email,
+    /// The first DNS name of the subject alternative names
+    Dns,
+    /// The first URI of the subject alternative names
+    Uri,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct CertificateAuthCommand {
+
+    /// The protected folders
+    #[clap(long, default_value_t = String::from("root"))]
+    pub protected_folders: String,
+
+    /// PEM bundle of the certificate authorities which issue the client certificates
+    #[clap(long)]
+    pub client_ca: String,
+
+    /// PEM or DER certificate revocation list of the client certificate authorities. Can be repeated
+    #[clap(long)]
+    pub crl: Vec<String>,
+
+    /// Whether the TLS handshake already fails without a client certificate
+    #[clap(long, value_enum, default_value_t = ClientAuth::Optional)]
+    pub client_auth: ClientAuth,
+
+    /// The part of the certificate used as user name
+    #[clap(long, value_enum, default_value_t = CertificateIdentity::Cn)]
+    pub identity: CertificateIdentity,
+
+    /// Apache-style htgroup file with lines like "finance: alice bob", used by the groups of the ACL rules
+    #[clap(long)]
+    pub htgroup: Option<String>,
+
+    /// Access rule for a path prefix or glob, e.g. "/reports/**/*.pdf;realm=Reports;users=alice;groups=finance;methods=GET,HEAD".
+    /// "public" opens a path up. Can be repeated, the most specific rule wins over the protected folders.
+    #[clap(long, value_parser = parse_acl_rule)]
+    pub acl: Vec<AclRule>,
+}
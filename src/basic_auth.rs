@@ -1,32 +1,205 @@
+use rustls::pki_types::CertificateDer;
+use subtle::ConstantTimeEq;
+
+use crate::acl::{AclRule, find_acl_rule, PathPattern};
 use crate::args::{AuthMode, BasicAuthCommand};
+use crate::bearer_auth::{BearerIdentity, verify_bearer};
+use crate::client_certificate::certificate_identity;
+use crate::digest_auth::{DigestVerification, generate_digest_challenges, verify_digest};
+use crate::header_parser::HeaderMap;
+use crate::htpasswd::{htdigest_ha1s, htgroup_contains, htpasswd_contains, verify_htpasswd};
+use crate::http_parser::{BasicCredentials, decode_user_name_password, find_basic_authorization_header,
+                         find_bearer_authorization_header, find_digest_authorization_header, Uri};
+use crate::string_operations::escape_quoted;
 use crate::RunCommand;
 
-fn extract_basic_auth_folders(auth_folders: &String) -> Vec<&str> {
-    let splits = auth_folders.split(",").map(|s| s.trim());
-    return splits.collect::<Vec<&str>>()
+fn extract_basic_auth_folders(auth_folders: &str) -> Vec<&str> {
+    let splits = auth_folders.split(',').map(|s| s.trim());
+    splits.collect::<Vec<&str>>()
+}
+
+/// The authentication settings together with the rule protecting the requested path
+pub(crate) struct Protection<'a> {
+    pub(crate) auth_mode: &'a AuthMode,
+    pub(crate) rule: AclRule,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum Authorization {
+    Granted,
+    /// Missing or wrong credentials, or a user the rule does not list
+    Unauthorized,
+    /// Valid digest credentials for a nonce which expired, so the client can retry without asking the user
+    Stale,
+    /// A bearer token which failed validation, with the reason
+    InvalidToken(&'static str),
+    /// A valid bearer token of a user the rule does not list
+    InsufficientScope,
+    /// The rule does not allow the method, or the client certificate is missing or names a user the rule does not list
+    Forbidden,
 }
 
-pub(crate) fn process_basic_auth<'a>(uri: &'a String, run_args: &'a RunCommand) -> Option<&'a BasicAuthCommand> {
+/// Finds the most specific rule for the path among the ACL rules and the protected folders,
+/// returning None when the path needs no authentication
+pub(crate) fn process_basic_auth<'a>(uri: &str, run_args: &'a RunCommand) -> Option<Protection<'a>> {
     let auth_mode = &run_args.auth_mode;
-    match auth_mode {
+    let (protected_folders, acl) = match auth_mode {
+        AuthMode::Basic(basic_auth_command) => (&basic_auth_command.protected_folders, &basic_auth_command.acl),
+        AuthMode::Digest(digest_auth_command) => (&digest_auth_command.protected_folders, &digest_auth_command.acl),
+        AuthMode::Bearer(bearer_auth_command) => (&bearer_auth_command.protected_folders, &bearer_auth_command.acl),
+        AuthMode::Certificate(certificate_auth_command) =>
+            (&certificate_auth_command.protected_folders, &certificate_auth_command.acl),
+        AuthMode::None(_) => return None
+    };
+    let folder_rules: Vec<AclRule> = extract_basic_auth_folders(protected_folders).iter()
+        .filter_map(|folder| PathPattern::parse(folder).ok())
+        .map(AclRule::new)
+        .collect();
+    match find_acl_rule(uri, acl.iter().chain(folder_rules.iter())) {
+        Some(rule) if !rule.public => {
+            println!("Found rule: {:?}", rule.pattern);
+            Some(Protection { auth_mode, rule: rule.clone() })
+        }
+        _ => None
+    }
+}
+
+/// Verifies the credentials of the Authorization header or the client certificate of the TLS session,
+/// then checks the user and the method against the rule
+pub(crate) fn authorize(protection: &Protection, headers: &HeaderMap, method: &str, request_uri: &Uri,
+                        client_certificate: Option<&CertificateDer>) -> Authorization {
+    let rule = &protection.rule;
+    let (username, groups, htgroup) = match protection.auth_mode {
         AuthMode::Basic(basic_auth_command) => {
-            let protected_folders = &basic_auth_command.protected_folders;
-            let folders_vec = extract_basic_auth_folders(protected_folders);
-            let matches = folders_vec.iter().find(|&&s| uri.starts_with(s));
-            if matches.is_some() {
-                let found = *matches.unwrap();
-                println!("Found folder: {found}");
-                return Some(basic_auth_command)
+            let credentials = find_basic_authorization_header(headers)
+                .and_then(|authentication| decode_user_name_password(&authentication));
+            match credentials {
+                Some(credentials) if verify_credentials(basic_auth_command, &credentials) =>
+                    (credentials.username, vec![], &basic_auth_command.htgroup),
+                _ => return Authorization::Unauthorized
             }
         }
-        AuthMode::None(_) => {}
+        AuthMode::Digest(digest_auth_command) => {
+            let verification = find_digest_authorization_header(headers)
+                .map(|digest| verify_digest(digest_auth_command, &digest, &rule.realm, method, request_uri));
+            match verification {
+                Some(DigestVerification::Valid(username)) => (username, vec![], &digest_auth_command.htgroup),
+                Some(DigestVerification::Stale) => return Authorization::Stale,
+                _ => return Authorization::Unauthorized
+            }
+        }
+        AuthMode::Bearer(bearer_auth_command) => {
+            let Some(token) = find_bearer_authorization_header(headers) else {
+                return Authorization::Unauthorized;
+            };
+            match verify_bearer(bearer_auth_command, &token) {
+                Ok(BearerIdentity { username, groups }) => (username, groups, &bearer_auth_command.htgroup),
+                Err(description) => {
+                    println!("Rejected bearer token: {description}");
+                    return Authorization::InvalidToken(description);
+                }
+            }
+        }
+        AuthMode::Certificate(certificate_auth_command) => {
+            let identity = client_certificate
+                .and_then(|certificate| certificate_identity(certificate, certificate_auth_command.identity));
+            match identity {
+                Some(username) => (username, vec![], &certificate_auth_command.htgroup),
+                None => {
+                    println!("No client certificate naming a user");
+                    return Authorization::Forbidden;
+                }
+            }
+        }
+        AuthMode::None(_) => return Authorization::Granted
+    };
+    let anyone = rule.users.is_empty() && rule.groups.is_empty();
+    let listed = anyone
+        || rule.users.contains(&username)
+        || rule.groups.iter().any(|group| groups.contains(group))
+        || rule.groups.iter().any(|group| match htgroup {
+            Some(htgroup) => htgroup_contains(htgroup, group, &username),
+            None => false
+        });
+    if !listed {
+        println!("User {username} is not allowed in realm {}", rule.realm);
+        return match protection.auth_mode {
+            AuthMode::Bearer(_) => Authorization::InsufficientScope,
+            AuthMode::Certificate(_) => Authorization::Forbidden,
+            _ => Authorization::Unauthorized
+        };
+    }
+    if !rule.allows_method(method) {
+        return Authorization::Forbidden;
+    }
+    Authorization::Granted
+}
+
+/// The user name the client tries to authenticate as, without verifying the credentials. None for unknown users,
+/// so guessed names don't each get a lockout record. Bearer tokens name no user up front.
+pub(crate) fn attempted_username(protection: &Protection, headers: &HeaderMap) -> Option<String> {
+    match protection.auth_mode {
+        AuthMode::Basic(basic_auth_command) => find_basic_authorization_header(headers)
+            .and_then(|authentication| decode_user_name_password(&authentication))
+            .map(|credentials| credentials.username)
+            .filter(|username| match &basic_auth_command.htpasswd {
+                Some(htpasswd) => htpasswd_contains(htpasswd, username),
+                None => *username == basic_auth_command.username
+            }),
+        AuthMode::Digest(digest_auth_command) => find_digest_authorization_header(headers)
+            .and_then(|digest| digest.param("username").map(|username| username.to_string()))
+            .filter(|username| match &digest_auth_command.htdigest {
+                Some(htdigest) => !htdigest_ha1s(htdigest, username, &protection.rule.realm).is_empty(),
+                None => *username == digest_auth_command.username
+            }),
+        _ => None
+    }
+}
+
+/// The WWW-Authenticate challenges asking for credentials of the realm of the rule,
+/// telling bearer clients why their token was not accepted (RFC 6750, section 3)
+pub(crate) fn generate_challenges(protection: &Protection, authorization: &Authorization) -> Vec<String> {
+    let realm = &protection.rule.realm;
+    match protection.auth_mode {
+        AuthMode::Digest(digest_auth_command) =>
+            generate_digest_challenges(digest_auth_command, realm, *authorization == Authorization::Stale),
+        AuthMode::Bearer(_) => {
+            let error = match authorization {
+                Authorization::InvalidToken(description) =>
+                    format!(", error=\"invalid_token\", error_description=\"{}\"", escape_quoted(description)),
+                Authorization::InsufficientScope =>
+                    ", error=\"insufficient_scope\", error_description=\"The token does not grant access to this realm\"".to_string(),
+                _ => String::new()
+            };
+            vec![format!("Bearer realm=\"{}\"{error}", escape_quoted(realm))]
+        }
+        _ => vec![format!("Basic realm=\"{}\", charset=\"UTF-8\"", escape_quoted(realm))]
+    }
+}
+
+/// Checks the credentials against the htpasswd file when there is one, otherwise against the command line user.
+/// Both comparisons take the same time, wherever the credentials differ.
+pub(crate) fn verify_credentials(basic_auth_command: &BasicAuthCommand, credentials: &BasicCredentials) -> bool {
+    match &basic_auth_command.htpasswd {
+        Some(htpasswd) => verify_htpasswd(htpasswd, &credentials.username, &credentials.password),
+        None => {
+            let username_matches = credentials.username.as_bytes().ct_eq(basic_auth_command.username.as_bytes());
+            let password_matches = credentials.password.as_bytes().ct_eq(basic_auth_command.password.as_bytes());
+            (username_matches & password_matches).into()
+        }
     }
-    None
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::args::{BasicAuthCommand, NoneAuthCommand};
+    use base64::engine::general_purpose;
+    use clap::Parser;
+    use base64::Engine;
+
+    use crate::acl::parse_acl_rule;
+    use crate::args::{BasicAuthCommand, BearerAuthCommand, CertificateAuthCommand, CertificateIdentity, ClientAuth,
+                      DigestAlgorithm, DigestAuthCommand, HttpServerArgs, Mode, NoneAuthCommand};
+    use crate::client_certificate::tests::agent_certificate;
     use super::*;
 
     #[test]
@@ -41,36 +214,57 @@ mod tests {
     #[test]
     fn when_process_basic_auth_should_not_process() {
         let uri = "/mdm-reports".to_string();
-        let run_cmd = RunCommand {
-            auth_mode: AuthMode::None(NoneAuthCommand{}),
-            root_folder: "/tmp".to_string(),
-            port: 80,
-            host: "0.0.0.0".to_string(),
-            pool_size: 4
-        };
-        assert_eq!(process_basic_auth(&uri, &run_cmd), false);
+        let run_cmd = run_command_factory(AuthMode::None(NoneAuthCommand{}));
+        assert!(process_basic_auth(&uri, &run_cmd).is_none());
     }
 
     #[test]
     fn when_process_basic_auth_should_process() {
         let uri = "/mdm-reports".to_string();
-        let run_cmd = RunCommand {
-            auth_mode: AuthMode::Basic(BasicAuthCommand{
-                protected_folders: uri.clone(),
-                username: "root".to_string(),
-                password: "test".to_string()
-            }),
-            root_folder: "/tmp".to_string(),
-            port: 80,
-            host: "0.0.0.0".to_string(),
-            pool_size: 4
+        let run_cmd = run_command_factory(AuthMode::Basic(BasicAuthCommand{
+            protected_folders: uri.clone(),
+            htpasswd: None,
+            htgroup: None,
+            acl: vec![],
+            username: "root".to_string(),
+            password: "test".to_string()
+        }));
+        assert!(process_basic_auth(&uri, &run_cmd).is_some());
+    }
+
+    #[test]
+    fn when_verify_credentials_should_require_both_to_match() {
+        let basic_auth_command = BasicAuthCommand {
+            protected_folders: "/".to_string(),
+            htpasswd: None,
+            htgroup: None,
+            acl: vec![],
+            username: "root".to_string(),
+            password: "test".to_string()
+        };
+        let credentials = |username: &str, password: &str| BasicCredentials {
+            username: username.to_string(),
+            password: password.to_string()
         };
-        assert_eq!(process_basic_auth(&uri, &run_cmd), true);
+        assert!(verify_credentials(&basic_auth_command, &credentials("root", "test")));
+        assert!(!verify_credentials(&basic_auth_command, &credentials("root", "wrong")));
+        assert!(!verify_credentials(&basic_auth_command, &credentials("admin", "test")));
+    }
+
+    fn run_command_factory(auth_mode: AuthMode) -> RunCommand {
+        let args = ["http_server", "run", "--port", "80", "--host", "0.0.0.0", "--root-folder", "/tmp", "none"];
+        match HttpServerArgs::parse_from(args).mode {
+            Mode::Run(run_command) => RunCommand { auth_mode, ..*run_command },
+            _ => unreachable!()
+        }
     }
 
-    fn basic_auth_factory(protected_folders: &String) -> AuthMode {
+    fn basic_auth_factory(protected_folders: &str) -> AuthMode {
         AuthMode::Basic(BasicAuthCommand {
-            protected_folders: protected_folders.clone(),
+            protected_folders: protected_folders.to_string(),
+            htpasswd: None,
+            htgroup: None,
+            acl: vec![],
             username: "root".to_string(),
             password: "test".to_string()
         })
@@ -80,13 +274,151 @@ mod tests {
     fn when_process_basic_auth_multiple_folders_should_process() {
         let uri = "/mdm-reports".to_string();
         let protected_folders = "/api,/test,/mdm-reports".to_string();
-        let run_cmd = RunCommand {
-            auth_mode: basic_auth_factory(&protected_folders),
-            root_folder: "/tmp".to_string(),
-            port: 80,
-            host: "0.0.0.0".to_string(),
-            pool_size: 4
+        let run_cmd = run_command_factory(basic_auth_factory(&protected_folders));
+        assert!(process_basic_auth(&uri, &run_cmd).is_some());
+        assert!(process_basic_auth("/apiary", &run_cmd).is_none());
+    }
+
+    #[test]
+    fn when_authorize_should_check_users_and_methods() {
+        let auth_mode = basic_auth_factory("/");
+        let protection = |rule: &str| Protection {
+            auth_mode: &auth_mode,
+            rule: parse_acl_rule(rule).unwrap(),
+        };
+        let headers = |user_and_password: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Authorization", &format!("Basic {}", general_purpose::STANDARD.encode(user_and_password)));
+            headers
         };
-        assert_eq!(process_basic_auth(&uri, &run_cmd), true);
+        let credentials = headers("root:test");
+        let wrong = headers("root:wrong");
+        let uri = Uri::parse("/").unwrap();
+        assert_eq!(authorize(&protection("/;methods=GET"), &credentials, "GET", &uri, None), Authorization::Granted);
+        assert_eq!(authorize(&protection("/;methods=GET"), &credentials, "DELETE", &uri, None), Authorization::Forbidden);
+        assert_eq!(authorize(&protection("/"), &wrong, "GET", &uri, None), Authorization::Unauthorized);
+        assert_eq!(authorize(&protection("/"), &HeaderMap::new(), "GET", &uri, None), Authorization::Unauthorized);
+        assert_eq!(authorize(&protection("/;users=alice"), &credentials, "GET", &uri, None), Authorization::Unauthorized);
+        assert_eq!(authorize(&protection("/;users=alice,root"), &credentials, "GET", &uri, None), Authorization::Granted);
+    }
+
+    #[test]
+    fn when_attempted_username_is_unknown_should_be_none() {
+        let auth_mode = basic_auth_factory("/");
+        let protection = Protection { auth_mode: &auth_mode, rule: parse_acl_rule("/").unwrap() };
+        let headers = |user_and_password: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Authorization", &format!("Basic {}", general_purpose::STANDARD.encode(user_and_password)));
+            headers
+        };
+        assert_eq!(attempted_username(&protection, &headers("root:wrong")), Some("root".to_string()));
+        assert_eq!(attempted_username(&protection, &headers("guessed:wrong")), None);
+    }
+
+    #[test]
+    fn when_authorize_digest_should_ignore_basic_credentials() {
+        let auth_mode = AuthMode::Digest(DigestAuthCommand {
+            protected_folders: "/".to_string(),
+            htdigest: None,
+            htgroup: None,
+            acl: vec![],
+            algorithms: vec![DigestAlgorithm::Sha256],
+            nonce_lifetime: 300,
+            username: "root".to_string(),
+            password: "test".to_string()
+        });
+        let protection = Protection { auth_mode: &auth_mode, rule: parse_acl_rule("/;realm=Digest").unwrap() };
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", &format!("Basic {}", general_purpose::STANDARD.encode("root:test")));
+        assert_eq!(authorize(&protection, &headers, "GET", &Uri::parse("/").unwrap(), None), Authorization::Unauthorized);
+        let challenges = generate_challenges(&protection, &Authorization::Stale);
+        assert!(challenges[0].starts_with("Digest realm=\"Digest\", qop=\"auth\", algorithm=SHA-256, nonce="));
+        assert!(challenges[0].ends_with(", stale=true"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn when_authorize_bearer_should_describe_rejections() {
+        let tokens = std::env::temp_dir().join(format!("http_server_bearer_tokens_{}", std::process::id()));
+        std::fs::write(&tokens, "token-1 deploy ops\n").unwrap();
+        let auth_mode = AuthMode::Bearer(BearerAuthCommand {
+            protected_folders: "/".to_string(),
+            tokens: Some(tokens.to_str().unwrap().to_string()),
+            jwt_secret: None,
+            jwt_public_key: None,
+            jwks: None,
+            audience: None,
+            issuer: None,
+            username_claim: "sub".to_string(),
+            groups_claim: "groups".to_string(),
+            leeway: 60,
+            htgroup: None,
+            acl: vec![],
+        });
+        let protection = |rule: &str| Protection { auth_mode: &auth_mode, rule: parse_acl_rule(rule).unwrap() };
+        let headers = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Authorization", &format!("Bearer {token}"));
+            headers
+        };
+        let uri = Uri::parse("/").unwrap();
+        assert_eq!(authorize(&protection("/;groups=ops"), &headers("token-1"), "GET", &uri, None), Authorization::Granted);
+        assert_eq!(authorize(&protection("/;groups=finance"), &headers("token-1"), "GET", &uri, None), Authorization::InsufficientScope);
+        let invalid = authorize(&protection("/"), &headers("token-2"), "GET", &uri, None);
+        assert_eq!(invalid, Authorization::InvalidToken("The token is unknown"));
+        assert_eq!(generate_challenges(&protection("/;realm=Api"), &invalid),
+                   vec!["Bearer realm=\"Api\", error=\"invalid_token\", error_description=\"The token is unknown\""]);
+        assert_eq!(authorize(&protection("/"), &HeaderMap::new(), "GET", &uri, None), Authorization::Unauthorized);
+        assert_eq!(generate_challenges(&protection("/;realm=Api"), &Authorization::Unauthorized), vec!["Bearer realm=\"Api\""]);
+        std::fs::remove_file(tokens).unwrap();
+    }
+
+    #[test]
+    fn when_authorize_certificate_should_map_subject_to_user() {
+        let auth_mode = AuthMode::Certificate(CertificateAuthCommand {
+            protected_folders: "/".to_string(),
+            client_ca: "ca.pem".to_string(),
+            crl: vec![],
+            client_auth: ClientAuth::Optional,
+            identity: CertificateIdentity::Cn,
+            htgroup: None,
+            acl: vec![],
+        });
+        let protection = |rule: &str| Protection { auth_mode: &auth_mode, rule: parse_acl_rule(rule).unwrap() };
+        let uri = Uri::parse("/").unwrap();
+        let certificate = agent_certificate();
+        assert_eq!(authorize(&protection("/;users=build-agent-7"), &HeaderMap::new(), "GET", &uri, Some(&certificate)),
+                   Authorization::Granted);
+        assert_eq!(authorize(&protection("/;users=alice"), &HeaderMap::new(), "GET", &uri, Some(&certificate)),
+                   Authorization::Forbidden);
+        assert_eq!(authorize(&protection("/"), &HeaderMap::new(), "GET", &uri, None), Authorization::Forbidden);
+    }
+
+    #[test]
+    fn when_process_basic_auth_with_acl_should_use_most_specific_rule() {
+        let run_cmd = run_command_factory(AuthMode::Basic(BasicAuthCommand {
+            protected_folders: "/data".to_string(),
+            htpasswd: None,
+            htgroup: None,
+            acl: vec![parse_acl_rule("/data/public;public").unwrap(), parse_acl_rule("/data/*.csv;realm=Csv").unwrap()],
+            username: "root".to_string(),
+            password: "test".to_string()
+        }));
+        assert_eq!(process_basic_auth("/data/a.txt", &run_cmd).unwrap().rule.realm, "User Visible Realm");
+        assert_eq!(process_basic_auth("/data/a.csv", &run_cmd).unwrap().rule.realm, "Csv");
+        assert!(process_basic_auth("/data/public/a.csv", &run_cmd).is_none());
+    }
+
+    #[test]
+    fn when_acl_rule_and_protected_folder_have_same_prefix_should_use_acl_rule() {
+        let run_cmd = run_command_factory(AuthMode::Basic(BasicAuthCommand {
+            protected_folders: "/data,/reports/".to_string(),
+            htpasswd: None,
+            htgroup: None,
+            acl: vec![parse_acl_rule("/data;users=alice;methods=GET").unwrap(), parse_acl_rule("/reports;realm=Reports").unwrap()],
+            username: "root".to_string(),
+            password: "test".to_string()
+        }));
+        assert_eq!(process_basic_auth("/data/a.txt", &run_cmd).unwrap().rule.users, vec!["alice".to_string()]);
+        assert_eq!(process_basic_auth("/reports/a.txt", &run_cmd).unwrap().rule.realm, "Reports");
+    }
+}
@@ -0,0 +1,226 @@
+use std::{fmt, io, str};
+use std::io::{BufRead, Read};
+
+use nom::bytes::complete::{take_while, take_while1};
+use nom::character::is_hex_digit;
+use nom::IResult;
+
+use crate::header_parser::{crlf, HeaderMap, message_headers};
+
+const MAX_LINE_SIZE: u64 = 8 * 1024;
+
+/// The request body together with the trailer fields of a chunked message
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub(crate) struct Body {
+    pub(crate) content: Vec<u8>,
+    pub(crate) trailers: HeaderMap,
+}
+
+#[derive(Debug)]
+pub(crate) enum BodyError {
+    /// The body is bigger than the configured maximum body size
+    TooLarge,
+    /// The message framing cannot be understood
+    Malformed(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BodyError::TooLarge => write!(f, "Request body too large"),
+            BodyError::Malformed(message) => write!(f, "Malformed request body: {message}"),
+            BodyError::Io(e) => write!(f, "Cannot read request body: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for BodyError {
+    fn from(e: io::Error) -> Self {
+        BodyError::Io(e)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum BodyLength {
+    Empty,
+    Fixed(usize),
+    Chunked,
+}
+
+/// Determines how the request body is framed according to RFC 9112, section 6.3
+pub(crate) fn body_length(headers: &HeaderMap) -> Result<BodyLength, BodyError> {
+    let transfer_codings = headers.get_all("Transfer-Encoding").iter()
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_string())
+        .filter(|coding| !coding.is_empty())
+        .collect::<Vec<String>>();
+    if !transfer_codings.is_empty() {
+        if headers.contains("Content-Length") {
+            return Err(BodyError::Malformed("Both Transfer-Encoding and Content-Length sent".to_string()));
+        }
+        return match transfer_codings.last() {
+            Some(coding) if coding.eq_ignore_ascii_case("chunked") && transfer_codings.len() == 1 => Ok(BodyLength::Chunked),
+            _ => Err(BodyError::Malformed(format!("Unsupported transfer coding {:?}", transfer_codings)))
+        };
+    }
+
+    // Content-Length is 1*DIGIT, a sign which parse accepts could be framed differently by a proxy in front
+    let lengths = headers.get_all("Content-Length").iter()
+        .flat_map(|value| value.split(','))
+        .map(|length| length.trim())
+        .map(|length| if !length.is_empty() && length.bytes().all(|b| b.is_ascii_digit()) {
+            length.parse::<usize>().map_err(|e| e.to_string())
+        } else {
+            Err(format!("{length:?} is no number"))
+        })
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|e| BodyError::Malformed(format!("Invalid Content-Length: {e}")))?;
+    match lengths.first() {
+        Some(length) if lengths.iter().any(|other| other != length) =>
+            Err(BodyError::Malformed("Conflicting Content-Length values".to_string())),
+        Some(0) | None => Ok(BodyLength::Empty),
+        Some(length) => Ok(BodyLength::Fixed(*length))
+    }
+}
+
+/// Reads the request body from the connection, never accepting more than max_body_size bytes
+pub(crate) fn read_body(reader: &mut impl BufRead, body_length: &BodyLength,
+                        max_body_size: usize) -> Result<Body, BodyError> {
+    match body_length {
+        BodyLength::Empty => Ok(Body::default()),
+        BodyLength::Fixed(length) => {
+            if *length > max_body_size {
+                return Err(BodyError::TooLarge);
+            }
+            let mut content = vec![0; *length];
+            reader.read_exact(&mut content)?;
+            Ok(Body { content, trailers: HeaderMap::new() })
+        }
+        BodyLength::Chunked => read_chunked_body(reader, max_body_size)
+    }
+}
+
+fn read_chunked_body(reader: &mut impl BufRead, max_body_size: usize) -> Result<Body, BodyError> {
+    let mut content = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let (_, size) = chunk_size_line(&line)
+            .map_err(|_| BodyError::Malformed(format!("Invalid chunk size line {:?}", String::from_utf8_lossy(&line))))?;
+        if size == 0 {
+            break;
+        }
+        if size > max_body_size - content.len() {
+            return Err(BodyError::TooLarge);
+        }
+        let start = content.len();
+        content.resize(start + size, 0);
+        reader.read_exact(&mut content[start..])?;
+        if read_line(reader)? != b"\r\n" {
+            return Err(BodyError::Malformed("Chunk data not followed by CRLF".to_string()));
+        }
+    }
+
+    let mut trailer_section = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        trailer_section.extend_from_slice(&line);
+        if line == b"\r\n" {
+            break;
+        }
+        if trailer_section.len() as u64 > MAX_LINE_SIZE {
+            return Err(BodyError::Malformed("Trailer section too large".to_string()));
+        }
+    }
+    let trailers = match message_headers(&trailer_section) {
+        Ok((b"\r\n", trailers)) => trailers,
+        _ => return Err(BodyError::Malformed("Invalid trailer section".to_string()))
+    };
+    Ok(Body { content, trailers })
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Vec<u8>, BodyError> {
+    let mut line = Vec::new();
+    reader.by_ref().take(MAX_LINE_SIZE).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\r\n") {
+        return Err(BodyError::Malformed("Line not terminated by CRLF".to_string()));
+    }
+    Ok(line)
+}
+
+/// Parses a chunk size line, ignoring any chunk extensions
+fn chunk_size_line(i: &[u8]) -> IResult<&[u8], usize> {
+    let (i, size) = take_while1(is_hex_digit)(i)?;
+    let (i, _) = take_while(|c| c != b'\r')(i)?;
+    let (i, _) = crlf(i)?;
+    let size = str::from_utf8(size).ok()
+        .and_then(|size| usize::from_str_radix(size, 16).ok())
+        .ok_or(nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::HexDigit)))?;
+    Ok((i, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    fn headers(header_str: &str) -> HeaderMap {
+        let (_, header_map) = message_headers(header_str.as_bytes()).unwrap();
+        header_map
+    }
+
+    #[test]
+    fn when_body_length_should_detect_framing() {
+        assert_eq!(body_length(&headers("Host: a\r\n\r\n")).unwrap(), BodyLength::Empty);
+        assert_eq!(body_length(&headers("Content-Length: 12\r\n\r\n")).unwrap(), BodyLength::Fixed(12));
+        assert_eq!(body_length(&headers("Transfer-Encoding: Chunked\r\n\r\n")).unwrap(), BodyLength::Chunked);
+    }
+
+    #[test]
+    fn when_body_length_ambiguous_should_err() {
+        assert!(body_length(&headers("Content-Length: 12\r\nTransfer-Encoding: chunked\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Content-Length: 12\r\nContent-Length: 13\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Content-Length: -1\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Content-Length: +5\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Content-Length: 5,+5\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Content-Length: 0x5\r\n\r\n")).is_err());
+        assert!(body_length(&headers("Transfer-Encoding: gzip\r\n\r\n")).is_err());
+    }
+
+    #[test]
+    fn when_read_body_fixed_should_leave_next_request() {
+        let mut reader = BufReader::new("helloGET / HTTP/1.1\r\n".as_bytes());
+        let body = read_body(&mut reader, &BodyLength::Fixed(5), 100).unwrap();
+        assert_eq!(body.content, b"hello");
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn when_read_body_chunked_should_decode_chunks_and_trailers() {
+        let message = "5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nChecksum: abc\r\n\r\nnext";
+        let mut reader = BufReader::new(message.as_bytes());
+        let body = read_body(&mut reader, &BodyLength::Chunked, 100).unwrap();
+        assert_eq!(body.content, b"hello, world");
+        assert_eq!(body.trailers.get("checksum"), Some("abc"));
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+
+    #[test]
+    fn when_read_body_too_large_should_err() {
+        let mut reader = BufReader::new("5\r\nhello\r\n0\r\n\r\n".as_bytes());
+        assert!(matches!(read_body(&mut reader, &BodyLength::Chunked, 4), Err(BodyError::TooLarge)));
+        let mut reader = BufReader::new("hello".as_bytes());
+        assert!(matches!(read_body(&mut reader, &BodyLength::Fixed(5), 4), Err(BodyError::TooLarge)));
+    }
+
+    #[test]
+    fn when_read_body_chunked_malformed_should_err() {
+        let mut reader = BufReader::new("zz\r\nhello\r\n0\r\n\r\n".as_bytes());
+        assert!(matches!(read_body(&mut reader, &BodyLength::Chunked, 100), Err(BodyError::Malformed(_))));
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::str;
+
+/// Only the beginning of a text file is inspected, so huge logs are not read twice
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+const UTF_8: &str = "utf-8";
+
+/// Detects the charset of a text file from its byte order mark or by validating it as UTF-8.
+/// Falls back to the default charset, or to no charset at all, and leaves the file at its start.
+pub(crate) fn detect_charset(file: &mut File, default_charset: Option<&str>) -> io::Result<Option<String>> {
+    let mut sample = Vec::new();
+    file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    file.seek(SeekFrom::Start(0))?;
+    let is_complete = (sample.len() as u64) < SAMPLE_SIZE;
+    Ok(charset_from_sample(&sample, is_complete, default_charset))
+}
+
+fn charset_from_sample(sample: &[u8], is_complete: bool, default_charset: Option<&str>) -> Option<String> {
+    if sample.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return Some(UTF_8.to_string());
+    }
+    if sample.starts_with(&[0xFE, 0xFF]) {
+        return Some("utf-16be".to_string());
+    }
+    if sample.starts_with(&[0xFF, 0xFE]) {
+        return Some("utf-16le".to_string());
+    }
+    match str::from_utf8(sample) {
+        Ok(_) => Some(UTF_8.to_string()),
+        // A multi-byte sequence cut off at the end of a partial sample is not an error
+        Err(e) if e.error_len().is_none() && !is_complete => Some(UTF_8.to_string()),
+        Err(_) => default_charset.map(|charset| charset.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_charset_from_sample_has_bom_should_use_bom() {
+        assert_eq!(charset_from_sample(&[0xEF, 0xBB, 0xBF, b'a'], true, None), Some("utf-8".to_string()));
+        assert_eq!(charset_from_sample(&[0xFF, 0xFE, b'a', 0], true, None), Some("utf-16le".to_string()));
+    }
+
+    #[test]
+    fn when_charset_from_sample_is_utf8_should_be_utf8() {
+        assert_eq!(charset_from_sample("Grüße".as_bytes(), true, None), Some("utf-8".to_string()));
+        let truncated = &"Grüße".as_bytes()[..3];
+        assert_eq!(charset_from_sample(truncated, false, None), Some("utf-8".to_string()));
+        assert_eq!(charset_from_sample(truncated, true, None), None);
+    }
+
+    #[test]
+    fn when_charset_from_sample_is_latin1_should_use_default() {
+        let latin1 = b"Gr\xFC\xDFe";
+        assert_eq!(charset_from_sample(latin1, true, Some("iso-8859-1")), Some("iso-8859-1".to_string()));
+        assert_eq!(charset_from_sample(latin1, true, None), None);
+    }
+}
@@ -0,0 +1,103 @@
+use rustls::pki_types::CertificateDer;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+
+use crate::args::CertificateIdentity;
+
+/// The parsed certificate, None when it is malformed or followed by trailing bytes
+fn parse_certificate<'a>(certificate: &'a CertificateDer) -> Option<X509Certificate<'a>> {
+    X509Certificate::from_der(certificate).ok()
+        .filter(|(rest, _)| rest.is_empty())
+        .map(|(_, parsed)| parsed)
+}
+
+fn common_name(certificate: &X509Certificate) -> Option<String> {
+    certificate.subject().iter_common_name()
+        .next()
+        .and_then(|common_name| common_name.as_str().ok())
+        .map(|common_name| common_name.to_string())
+}
+
+/// The alternative names of the certificate, empty when the extension is missing or present more than once
+fn subject_alt_names<'a>(certificate: &'a X509Certificate) -> Vec<&'a GeneralName<'a>> {
+    certificate.subject_alternative_name().ok()
+        .flatten()
+        .map(|extension| extension.value.general_names.iter().collect())
+        .unwrap_or_default()
+}
+
+/// The names a server certificate is valid for in lower case: its DNS alternative names,
+/// or the common name of a certificate without any
+pub(crate) fn certificate_server_names(certificate: &CertificateDer) -> Vec<String> {
+    let Some(parsed) = parse_certificate(certificate) else {
+        return vec![];
+    };
+    let dns_names: Vec<String> = subject_alt_names(&parsed).into_iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+            _ => None
+        })
+        .collect();
+    if !dns_names.is_empty() {
+        return dns_names;
+    }
+    common_name(&parsed).map(|common_name| common_name.to_ascii_lowercase()).into_iter().collect()
+}
+
+/// The user name carried by the client certificate, None when the certificate has no such name.
+/// The certificate itself was verified against the client CA during the handshake.
+pub(crate) fn certificate_identity(certificate: &CertificateDer, identity: CertificateIdentity) -> Option<String> {
+    let parsed = parse_certificate(certificate)?;
+    if identity == CertificateIdentity::Cn {
+        return common_name(&parsed);
+    }
+    subject_alt_names(&parsed).into_iter()
+        .find_map(|name| match (identity, name) {
+            (CertificateIdentity::Email, GeneralName::RFC822Name(name))
+            | (CertificateIdentity::Dns, GeneralName::DNSName(name))
+            | (CertificateIdentity::Uri, GeneralName::URI(name)) => Some(name.to_string()),
+            _ => None
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Self-signed, subject "O=Example CI, CN=build-agent-7", with DNS, e-mail and URI alternative names
+    const AGENT_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIICAzCCAaqgAwIBAgIUf1YNN//phZgY3/zeR8XDaMPaMLUwCgYIKoZIzj0EAwIw
+LTETMBEGA1UECgwKRXhhbXBsZSBDSTEWMBQGA1UEAwwNYnVpbGQtYWdlbnQtNzAe
+Fw0yNjEwMTcyMTAzNTBaFw0zNjEwMTQyMTAzNTBaMC0xEzARBgNVBAoMCkV4YW1w
+bGUgQ0kxFjAUBgNVBAMMDWJ1aWxkLWFnZW50LTcwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAAQpcMuqDtWTaH9D+3p5vsp0sCb407+dRQOPpl4jw4i/HGl1kPQjkbcO
+3duSGoAFe/36PpXAdXgIxTMfoX5Yf00uo4GnMIGkMB0GA1UdDgQWBBSnzjDsbTOU
+afWg9bHLjF4DSnyLFDAfBgNVHSMEGDAWgBSnzjDsbTOUafWg9bHLjF4DSnyLFDAP
+BgNVHRMBAf8EBTADAQH/MFEGA1UdEQRKMEiCFWFnZW50Ny5jaS5leGFtcGxlLmNv
+bYESYWdlbnQ3QGV4YW1wbGUuY29thhtzcGlmZmU6Ly9leGFtcGxlLmNvbS9hZ2Vu
+dDcwCgYIKoZIzj0EAwIDRwAwRAIgbXYptDeqwdps0jtk4wov5Q5RiO6uutHo1S2S
+GO/Tgz4CIHWVQo2QQw9Omv/Y97ghGWA4WTsECPzibZEn/MNqVa4A
+-----END CERTIFICATE-----";
+
+    pub(crate) fn agent_certificate() -> CertificateDer<'static> {
+        rustls_pemfile::certs(&mut AGENT_CERTIFICATE.as_bytes()).next().unwrap().unwrap()
+    }
+
+    #[test]
+    fn when_certificate_identity_should_read_subject_and_alt_names() {
+        let certificate = agent_certificate();
+        assert_eq!(certificate_identity(&certificate, CertificateIdentity::Cn).unwrap(), "build-agent-7");
+        assert_eq!(certificate_identity(&certificate, CertificateIdentity::Email).unwrap(), "agent7@example.com");
+        assert_eq!(certificate_identity(&certificate, CertificateIdentity::Dns).unwrap(), "agent7.ci.example.com");
+        assert_eq!(certificate_identity(&certificate, CertificateIdentity::Uri).unwrap(), "spiffe://example.com/agent7");
+    }
+
+    #[test]
+    fn when_certificate_is_truncated_should_find_no_identity() {
+        let certificate = agent_certificate();
+        let truncated = CertificateDer::from(&certificate[..100]);
+        assert!(certificate_identity(&truncated, CertificateIdentity::Cn).is_none());
+        assert!(certificate_identity(&CertificateDer::from(&[][..]), CertificateIdentity::Dns).is_none());
+    }
+}
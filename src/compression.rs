@@ -0,0 +1,241 @@
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use brotli::CompressorWriter;
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::args::ContentCoding;
+use crate::header_parser::HeaderMap;
+
+/// Compressed output is buffered, so that it is sent in chunks of this size
+pub(crate) const CHUNK_SIZE: usize = 16 * 1024;
+
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
+impl ContentCoding {
+    /// The token used in Accept-Encoding and Content-Encoding
+    pub(crate) fn token(&self) -> &'static str {
+        match self {
+            ContentCoding::Br => "br",
+            ContentCoding::Zstd => "zstd",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Codings for which precompressed sidecar files are looked up, in order of preference
+const SIDECAR_CODINGS: [ContentCoding; 3] = [ContentCoding::Br, ContentCoding::Zstd, ContentCoding::Gzip];
+
+/// The path of the precompressed variant of a file, e.g. app.js.br next to app.js
+pub(crate) fn sidecar_path(path: &str, coding: ContentCoding) -> Option<String> {
+    let extension = match coding {
+        ContentCoding::Br => "br",
+        ContentCoding::Zstd => "zst",
+        ContentCoding::Gzip => "gz",
+        ContentCoding::Deflate => return None
+    };
+    Some(format!("{path}.{extension}"))
+}
+
+/// Selects the best precompressed sidecar of the file accepted by the client, among the enabled codings
+pub(crate) fn find_sidecar(path: &str, headers: &HeaderMap, enabled: &[ContentCoding]) -> Option<(ContentCoding, String)> {
+    let available: Vec<ContentCoding> = SIDECAR_CODINGS.into_iter()
+        .filter(|coding| enabled.contains(coding))
+        .filter(|coding| sidecar_path(path, *coding).map(|sidecar| Path::new(&sidecar).is_file()).unwrap_or(false))
+        .collect();
+    let coding = negotiate_coding(headers, &available)?;
+    Some((coding, sidecar_path(path, coding)?))
+}
+
+/// Selects the content coding with the highest q-value in Accept-Encoding (RFC 9110, section 12.5.3).
+/// Ties are broken by the order of the available codings. None means the content is sent as is.
+pub(crate) fn negotiate_coding(headers: &HeaderMap, available: &[ContentCoding]) -> Option<ContentCoding> {
+    let accept_encoding = headers.get_all("Accept-Encoding").join(",");
+    if accept_encoding.trim().is_empty() {
+        return None;
+    }
+    let qvalues = coding_qvalues(&accept_encoding);
+    let wildcard = qvalues.iter().find(|(coding, _)| coding == "*").map(|(_, q)| *q);
+    let mut selected: Option<(ContentCoding, f32)> = None;
+    for coding in available {
+        let explicit = qvalues.iter()
+            .find(|(name, _)| name == coding.token() || (*coding == ContentCoding::Gzip && name == "x-gzip"))
+            .map(|(_, q)| *q);
+        let q = explicit.or(wildcard).unwrap_or(0.0);
+        if q > 0.0 && selected.map(|(_, selected_q)| q > selected_q).unwrap_or(true) {
+            selected = Some((*coding, q));
+        }
+    }
+    selected.map(|(coding, _)| coding)
+}
+
+/// Lowercase coding names with their q-values. Entries with an invalid q-value are dropped.
+fn coding_qvalues(accept_encoding: &str) -> Vec<(String, f32)> {
+    accept_encoding.split(',')
+        .filter_map(|element| {
+            let mut parameters = element.split(';');
+            let coding = parameters.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let mut q = 1.0;
+            for parameter in parameters {
+                if let Some((name, value)) = parameter.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        q = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                    }
+                }
+            }
+            Some((coding, q))
+        })
+        .collect()
+}
+
+/// Compresses everything read into the writer, returning the number of bytes read and the writer
+pub(crate) fn compress<W: Write>(reader: &mut impl Read, coding: ContentCoding, writer: W) -> io::Result<(u64, W)> {
+    match coding {
+        ContentCoding::Br => {
+            let mut encoder = CompressorWriter::new(writer, CHUNK_SIZE, BROTLI_QUALITY, BROTLI_WINDOW);
+            let copied = io::copy(reader, &mut encoder)?;
+            encoder.flush()?;
+            Ok((copied, encoder.into_inner()))
+        }
+        ContentCoding::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+            let copied = io::copy(reader, &mut encoder)?;
+            Ok((copied, encoder.finish()?))
+        }
+        ContentCoding::Gzip => {
+            let mut encoder = GzEncoder::new(writer, Compression::default());
+            let copied = io::copy(reader, &mut encoder)?;
+            Ok((copied, encoder.finish()?))
+        }
+        ContentCoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(writer, Compression::default());
+            let copied = io::copy(reader, &mut encoder)?;
+            Ok((copied, encoder.finish()?))
+        }
+    }
+}
+
+/// Frames everything written to it with the chunked transfer coding (RFC 9112, section 7.1)
+pub(crate) struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Buffers the writes, so that every chunk but the last one has the same size
+    pub(crate) fn buffered(inner: W) -> BufWriter<ChunkedWriter<W>> {
+        BufWriter::with_capacity(CHUNK_SIZE, ChunkedWriter { inner })
+    }
+
+    /// Writes the last chunk without trailers
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner.write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+
+    use crate::header_parser::message_headers;
+
+    use super::*;
+
+    const ALL_CODINGS: [ContentCoding; 4] = [ContentCoding::Br, ContentCoding::Zstd, ContentCoding::Gzip, ContentCoding::Deflate];
+
+    fn headers(header_str: &str) -> HeaderMap {
+        let (_, header_map) = message_headers(format!("{header_str}\r\n").as_bytes()).unwrap();
+        header_map
+    }
+
+    #[test]
+    fn when_negotiate_coding_should_prefer_highest_qvalue() {
+        let request_headers = headers("Accept-Encoding: gzip;q=0.8, deflate, br;q=0.5\r\n");
+        assert_eq!(negotiate_coding(&request_headers, &ALL_CODINGS), Some(ContentCoding::Deflate));
+        let request_headers = headers("Accept-Encoding: gzip, deflate, br, zstd\r\n");
+        assert_eq!(negotiate_coding(&request_headers, &ALL_CODINGS), Some(ContentCoding::Br));
+        assert_eq!(negotiate_coding(&request_headers, &[ContentCoding::Gzip]), Some(ContentCoding::Gzip));
+    }
+
+    #[test]
+    fn when_negotiate_coding_should_respect_wildcard_and_refusals() {
+        let request_headers = headers("Accept-Encoding: *, br;q=0\r\n");
+        assert_eq!(negotiate_coding(&request_headers, &ALL_CODINGS), Some(ContentCoding::Zstd));
+        let request_headers = headers("Accept-Encoding: identity\r\n");
+        assert_eq!(negotiate_coding(&request_headers, &ALL_CODINGS), None);
+        let request_headers = headers("Accept-Encoding: gzip;q=0, x-gzip;q=abc\r\n");
+        assert_eq!(negotiate_coding(&request_headers, &ALL_CODINGS), None);
+        assert_eq!(negotiate_coding(&headers("Host: a\r\n"), &ALL_CODINGS), None);
+    }
+
+    #[test]
+    fn when_find_sidecar_should_select_existing_accepted_file() {
+        let folder = std::env::temp_dir().join(format!("http_server_sidecar_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("app.js").to_str().unwrap().to_string();
+        std::fs::write(&path, "let a = 1;").unwrap();
+        std::fs::write(format!("{path}.gz"), "gzip").unwrap();
+        std::fs::write(format!("{path}.br"), "brotli").unwrap();
+
+        let brotli = find_sidecar(&path, &headers("Accept-Encoding: gzip, br\r\n"), &ALL_CODINGS);
+        let gzip = find_sidecar(&path, &headers("Accept-Encoding: gzip, zstd\r\n"), &ALL_CODINGS);
+        let missing = find_sidecar(&path, &headers("Accept-Encoding: zstd, deflate\r\n"), &ALL_CODINGS);
+        let disabled = find_sidecar(&path, &headers("Accept-Encoding: gzip, br\r\n"), &[ContentCoding::Gzip]);
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(brotli, Some((ContentCoding::Br, format!("{path}.br"))));
+        assert_eq!(gzip, Some((ContentCoding::Gzip, format!("{path}.gz"))));
+        assert_eq!(missing, None);
+        assert_eq!(disabled, Some((ContentCoding::Gzip, format!("{path}.gz"))));
+    }
+
+    #[test]
+    fn when_compress_gzip_chunked_should_round_trip() {
+        let content = "Hello compression! ".repeat(2000);
+        let writer = ChunkedWriter::buffered(Vec::new());
+        let (copied, writer) = compress(&mut content.as_bytes(), ContentCoding::Gzip, writer).unwrap();
+        let framed = writer.into_inner().map_err(|e| e.into_error()).unwrap().finish().unwrap();
+        assert_eq!(copied, content.len() as u64);
+        assert!(framed.ends_with(b"\r\n0\r\n\r\n"));
+
+        let mut compressed = Vec::new();
+        let mut rest = framed.as_slice();
+        loop {
+            let line_end = rest.windows(2).position(|window| window == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&rest[..line_end]).unwrap(), 16).unwrap();
+            if size == 0 {
+                break;
+            }
+            compressed.extend_from_slice(&rest[line_end + 2..line_end + 2 + size]);
+            rest = &rest[line_end + 2 + size + 2..];
+        }
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, content);
+    }
+}
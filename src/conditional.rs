@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use linked_hash_set::LinkedHashSet;
+use sha2::{Digest, Sha256};
+
+use crate::args::{ContentCoding, EtagMode};
+use crate::header_parser::HeaderMap;
+use crate::http_date::{format_http_date, parse_http_date, truncate_to_seconds};
+
+lazy_static! {
+    /// Content hashes by path, only valid while the file keeps the same size and modification time
+    static ref HASH_CACHE: Mutex<HashMap<String, (u64, SystemTime, String)>> = Mutex::new(HashMap::new());
+}
+
+/// The validators of the selected representation: its entity tag and its modification time
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<SystemTime>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+impl Validators {
+    pub(crate) fn from_metadata(path: &str, metadata: &Metadata, etag_mode: &EtagMode) -> Validators {
+        let last_modified = metadata.modified().ok();
+        let etag = match (etag_mode, last_modified) {
+            (EtagMode::None, _) => None,
+            (EtagMode::Hash, _) => content_hash(path, metadata)
+                .map_err(|e| println!("Cannot hash {path}: {:?}", e.to_string()))
+                .ok()
+                .map(|hash| format!("\"{hash}\"")),
+            (_, Some(modified)) => {
+                let nanos = modified.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or_default();
+                let weak = if *etag_mode == EtagMode::Weak { "W/" } else { "" };
+                Some(format!("{weak}\"{nanos:x}-{:x}\"", metadata.len()))
+            }
+            (_, None) => None
+        };
+        Validators { etag, last_modified }
+    }
+
+    /// A compressed representation needs its own entity tag, so the content coding is added to the opaque tag
+    pub(crate) fn for_coding(&self, coding: &str) -> Validators {
+        Validators {
+            etag: self.etag.as_ref().map(|etag| format!("{}-{coding}\"", etag.trim_end_matches('"'))),
+            last_modified: self.last_modified,
+        }
+    }
+
+    /// The validators of the representation sent with the content coding, or of the content itself without one
+    pub(crate) fn for_representation(&self, coding: Option<ContentCoding>) -> Validators {
+        match coding {
+            Some(coding) => self.for_coding(coding.token()),
+            None => self.clone()
+        }
+    }
+
+    /// The ETag and Last-Modified headers sent with 200, 206 and 304 responses
+    pub(crate) fn headers(&self) -> LinkedHashSet<String> {
+        let mut validator_headers = LinkedHashSet::new();
+        if let Some(etag) = &self.etag {
+            validator_headers.insert(format!("ETag: {etag}\r\n"));
+        }
+        if let Some(last_modified) = self.last_modified {
+            validator_headers.insert(format!("Last-Modified: {}\r\n", format_http_date(last_modified)));
+        }
+        validator_headers
+    }
+}
+
+fn content_hash(path: &str, metadata: &Metadata) -> io::Result<String> {
+    let modified = metadata.modified()?;
+    if let Some((length, cached_modified, hash)) = HASH_CACHE.lock().unwrap().get(path) {
+        if *length == metadata.len() && *cached_modified == modified {
+            return Ok(hash.clone());
+        }
+    }
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let hash = hasher.finalize().iter().map(|b| format!("{b:02x}")).collect::<String>();
+    HASH_CACHE.lock().unwrap().insert(path.to_string(), (metadata.len(), modified, hash.clone()));
+    Ok(hash)
+}
+
+/// Evaluates the preconditions in the order of RFC 9110, section 13.2.2
+pub(crate) fn evaluate_preconditions(headers: &HeaderMap, validators: &Validators, is_get_or_head: bool) -> Precondition {
+    let etag = validators.etag.as_deref();
+    let last_modified = validators.last_modified.map(truncate_to_seconds);
+
+    if let Some(if_match) = headers.get("If-Match") {
+        if !etag_list_matches(if_match, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let (Some(date), Some(last_modified)) = (headers.get("If-Unmodified-Since").and_then(parse_http_date), last_modified) {
+        if last_modified > date {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(if_none_match) = headers.get("If-None-Match") {
+        if etag_list_matches(if_none_match, etag, false) {
+            return if is_get_or_head { Precondition::NotModified } else { Precondition::Failed };
+        }
+    } else if is_get_or_head {
+        if let (Some(date), Some(last_modified)) = (headers.get("If-Modified-Since").and_then(parse_http_date), last_modified) {
+            if last_modified <= date {
+                return Precondition::NotModified;
+            }
+        }
+    }
+    Precondition::Proceed
+}
+
+/// Checks a list of entity tags or "*" against the current entity tag
+pub(crate) fn etag_list_matches(etag_list: &str, etag: Option<&str>, strong: bool) -> bool {
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false
+    };
+    if etag_list.trim() == "*" {
+        return true;
+    }
+    parse_etags(etag_list).iter()
+        .any(|candidate| if strong { strong_match(candidate, etag) } else { weak_match(candidate, etag) })
+}
+
+pub(crate) fn strong_match(left: &str, right: &str) -> bool {
+    !left.starts_with("W/") && !right.starts_with("W/") && left == right
+}
+
+fn weak_match(left: &str, right: &str) -> bool {
+    left.trim_start_matches("W/") == right.trim_start_matches("W/")
+}
+
+/// Splits a list of entity tags. Commas are valid inside entity tags, so the quotes are followed.
+fn parse_etags(etag_list: &str) -> Vec<&str> {
+    let mut etags = Vec::new();
+    let mut rest = etag_list;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        let opaque_start = if rest.starts_with("W/\"") { 2 } else if rest.starts_with('"') { 0 } else { return etags };
+        match rest[opaque_start + 1..].find('"') {
+            Some(end) => {
+                let etag_end = opaque_start + end + 2;
+                etags.push(&rest[..etag_end]);
+                rest = &rest[etag_end..];
+            }
+            None => return etags
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::header_parser::message_headers;
+
+    use super::*;
+
+    fn headers(header_str: &str) -> HeaderMap {
+        let (_, header_map) = message_headers(format!("{header_str}\r\n").as_bytes()).unwrap();
+        header_map
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(784_111_777_500)),
+        }
+    }
+
+    #[test]
+    fn when_for_coding_should_extend_etag() {
+        assert_eq!(validators().for_coding("gzip").etag.as_deref(), Some("\"abc-gzip\""));
+        let weak = Validators { etag: Some("W/\"abc\"".to_string()), last_modified: None };
+        assert_eq!(weak.for_coding("br").etag.as_deref(), Some("W/\"abc-br\""));
+    }
+
+    #[test]
+    fn when_parse_etags_should_respect_quotes() {
+        assert_eq!(parse_etags("\"a,b\", W/\"c\" ,\"d\""), vec!["\"a,b\"", "W/\"c\"", "\"d\""]);
+    }
+
+    #[test]
+    fn when_if_none_match_matches_should_be_not_modified() {
+        let request_headers = headers("If-None-Match: W/\"xyz\", W/\"abc\"\r\n");
+        assert_eq!(evaluate_preconditions(&request_headers, &validators(), true), Precondition::NotModified);
+        assert_eq!(evaluate_preconditions(&request_headers, &validators(), false), Precondition::Failed);
+    }
+
+    #[test]
+    fn when_if_none_match_takes_precedence_over_if_modified_since() {
+        let request_headers = headers("If-None-Match: \"other\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n");
+        assert_eq!(evaluate_preconditions(&request_headers, &validators(), true), Precondition::Proceed);
+    }
+
+    #[test]
+    fn when_if_modified_since_should_compare_seconds() {
+        let unchanged = headers("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n");
+        assert_eq!(evaluate_preconditions(&unchanged, &validators(), true), Precondition::NotModified);
+        let changed = headers("If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n");
+        assert_eq!(evaluate_preconditions(&changed, &validators(), true), Precondition::Proceed);
+    }
+
+    #[test]
+    fn when_if_match_fails_should_be_precondition_failed() {
+        assert_eq!(evaluate_preconditions(&headers("If-Match: \"xyz\"\r\n"), &validators(), true), Precondition::Failed);
+        assert_eq!(evaluate_preconditions(&headers("If-Match: W/\"abc\"\r\n"), &validators(), true), Precondition::Failed);
+        assert_eq!(evaluate_preconditions(&headers("If-Match: *\r\n"), &validators(), true), Precondition::Proceed);
+        let unmodified_since = headers("If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n");
+        assert_eq!(evaluate_preconditions(&unmodified_since, &validators(), true), Precondition::Failed);
+    }
+}
@@ -0,0 +1,754 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use clap::builder::Resettable;
+use clap::{Arg, ArgAction, Command, CommandFactory};
+use serde::de::{DeserializeSeed, Deserializer, Error, MapAccess, SeqAccess, Visitor};
+
+use crate::args::HttpServerArgs;
+
+const RUN_COMMAND: &str = "run";
+const CONFIG_ARG: &str = "config";
+const AUTH_TABLE: &str = "auth";
+const AUTH_MODE: &str = "mode";
+const RULE_PATH: &str = "path";
+const VHOST_TABLE: &str = "vhost";
+const SERVER_NAMES: &str = "server_names";
+/// Settings of the listeners, the connections and the lockouts, which are shared by all virtual hosts
+const SERVER_SETTINGS: &[&str] = &["port", "host", "listen", "tls_port", "tls_certs", "tls_versions", "tls_cipher_suites", "alpn",
+    "https_redirect", "pool_size", "keep_alive_timeout", "write_timeout", "max_requests", "unknown_host", "max_auth_failures",
+    "lockout_seconds", "max_lockout_seconds", "trusted_networks", "audit_log"];
+
+/// The command line options standing for one key of the file
+#[derive(Debug, PartialEq, Eq)]
+struct Setting {
+    id: String,
+    options: Vec<String>,
+}
+
+/// The settings of a configuration file, for the run command and for the auth mode, and those of its virtual hosts
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ConfigFile {
+    run: Vec<Setting>,
+    auth_mode: Option<String>,
+    auth: Vec<Setting>,
+    virtual_hosts: Vec<ConfigFile>,
+}
+
+/// The arguments of the server with the settings of the configuration file
+pub struct ConfigArgs {
+    pub args: Vec<OsString>,
+    /// The arguments of the run command for every virtual host
+    pub virtual_hosts: Vec<Vec<OsString>>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+/// Inserts the settings of the file given with --config into the arguments of the run command,
+/// leaving out the options the command line sets itself, so that those replace the values of the file.
+/// A virtual host gets the same arguments, with the settings of its block in place of the ones they replace
+/// and its auth table, when it has one, in place of the authentication of the server.
+pub fn merge_config_file(args: Vec<OsString>) -> Result<ConfigArgs, String> {
+    let mut command = HttpServerArgs::command();
+    command.build();
+    let run = command.find_subcommand(RUN_COMMAND).expect("The run command exists");
+    let Some(run_index) = args.iter().skip(1).position(|arg| !arg.to_string_lossy().starts_with('-')).map(|i| i + 1)
+        .filter(|i| args[*i] == RUN_COMMAND) else {
+        return Ok(ConfigArgs { args, virtual_hosts: vec![] });
+    };
+    let run_options = scan_options(run, &args[run_index + 1..]);
+    let Some(path) = run_options.config.clone() else {
+        return Ok(ConfigArgs { args, virtual_hosts: vec![] });
+    };
+    let config_file = load_config_file(run, &path)?;
+    let auth_index = run_options.subcommand_index.map(|i| run_index + 1 + i);
+    let auth_mode = auth_index.map(|i| args[i].to_string_lossy().to_string()).or(config_file.auth_mode.clone());
+    let auth_options = match (auth_index, &auth_mode) {
+        (Some(auth_index), Some(auth_mode)) => match run.find_subcommand(auth_mode) {
+            Some(auth) => scan_options(auth, &args[auth_index + 1..]).ids(),
+            None => vec![]
+        },
+        _ => vec![]
+    };
+
+    let file_options = |settings: &[Setting], set_on_command_line: &[String]| settings.iter()
+        .filter(|setting| !set_on_command_line.contains(&setting.id))
+        .flat_map(|setting| setting.options.iter().map(OsString::from))
+        .collect::<Vec<_>>();
+    let run_end = auth_index.unwrap_or(args.len());
+    let mut run_args = file_options(&config_file.run, &run_options.ids());
+    run_args.extend_from_slice(&args[run_index + 1..run_end]);
+    let mut auth_args = vec![];
+    if let Some(auth_mode) = auth_mode {
+        auth_args.push(OsString::from(&auth_mode));
+        // Settings of another mode than the one chosen on the command line do not apply
+        if config_file.auth_mode.as_ref() == Some(&auth_mode) {
+            auth_args.extend(file_options(&config_file.auth, &auth_options));
+        }
+        if let Some(auth_index) = auth_index {
+            auth_args.extend_from_slice(&args[auth_index + 1..]);
+        }
+    }
+
+    let virtual_hosts = config_file.virtual_hosts.iter().map(|virtual_host| {
+        let replaced = virtual_host.run.iter().map(|setting| setting.id.clone()).collect::<Vec<_>>();
+        let mut virtual_host_args = args[..=run_index].to_vec();
+        virtual_host_args.extend(file_options(&virtual_host.run, &[]));
+        virtual_host_args.extend(without_options(run, &run_args, &replaced));
+        match &virtual_host.auth_mode {
+            Some(auth_mode) => {
+                virtual_host_args.push(OsString::from(auth_mode));
+                virtual_host_args.extend(file_options(&virtual_host.auth, &[]));
+            }
+            None => virtual_host_args.extend_from_slice(&auth_args)
+        }
+        virtual_host_args
+    }).collect();
+    let mut merged = args[..=run_index].to_vec();
+    merged.extend(run_args);
+    merged.extend(auth_args);
+    Ok(ConfigArgs { args: merged, virtual_hosts })
+}
+
+/// The options found in the arguments of a command, up to its subcommand, with the arguments they take up
+struct ScannedOptions {
+    options: Vec<(String, Range<usize>)>,
+    config: Option<String>,
+    subcommand_index: Option<usize>,
+}
+
+impl ScannedOptions {
+    fn ids(&self) -> Vec<String> {
+        self.options.iter().map(|(id, _)| id.clone()).collect()
+    }
+}
+
+/// The arguments without the options of the ids and their values
+fn without_options(command: &Command, args: &[OsString], ids: &[String]) -> Vec<OsString> {
+    let removed = scan_options(command, args).options.into_iter()
+        .filter(|(id, _)| ids.contains(id))
+        .map(|(_, range)| range)
+        .collect::<Vec<_>>();
+    args.iter().enumerate()
+        .filter(|(i, _)| !removed.iter().any(|range| range.contains(i)))
+        .map(|(_, arg)| arg.clone())
+        .collect()
+}
+
+fn scan_options(command: &Command, args: &[OsString]) -> ScannedOptions {
+    let mut scanned = ScannedOptions { options: vec![], config: None, subcommand_index: None };
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy();
+        let (option, inline_value) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = long.split_once('=').map(|(name, value)| (name, Some(value))).unwrap_or((long, None));
+            (command.get_arguments().find(|option| option.get_long() == Some(name)), value.map(|value| value.to_string()))
+        } else if let Some(short) = arg.strip_prefix('-').filter(|short| !short.is_empty()) {
+            let mut chars = short.chars();
+            let name = chars.next();
+            let value = Some(chars.as_str().to_string()).filter(|value| !value.is_empty());
+            (command.get_arguments().find(|option| option.get_short() == name), value)
+        } else {
+            scanned.subcommand_index = Some(i);
+            break;
+        };
+        let Some(option) = option else {
+            i += 1;
+            continue;
+        };
+        let start = i;
+        let value = if takes_value(option) && inline_value.is_none() {
+            i += 1;
+            args.get(i).map(|value| value.to_string_lossy().to_string())
+        } else {
+            inline_value
+        };
+        if option.get_id() == CONFIG_ARG {
+            scanned.config = value;
+        }
+        i += 1;
+        scanned.options.push((option.get_id().to_string(), start..i));
+    }
+    scanned
+}
+
+fn takes_value(option: &Arg) -> bool {
+    matches!(option.get_action(), ArgAction::Set | ArgAction::Append)
+}
+
+fn load_config_file(run: &Command, path: &str) -> Result<ConfigFile, String> {
+    let format = match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => Format::Toml,
+        Some("yaml" | "yml") => Format::Yaml,
+        _ => return Err(format!("Unknown format of {path}, use a .toml, .yaml or .yml file"))
+    };
+    let content = fs::read_to_string(path).map_err(|e| format!("Cannot read {path}: {e}"))?;
+    parse_config_file(run, &content, format).map_err(|e| format!("Invalid configuration in {path}: {e}"))
+}
+
+/// Parses the file, checking every value with the parser of its option, so the errors point at the line of the value
+fn parse_config_file(run: &Command, content: &str, format: Format) -> Result<ConfigFile, String> {
+    let seed = ConfigSeed { run, virtual_host: false };
+    match format {
+        Format::Toml => seed.deserialize(toml::Deserializer::new(content)).map_err(|e| e.to_string()),
+        Format::Yaml => seed.deserialize(serde_yaml::Deserializer::from_str(content)).map_err(|e| e.to_string()),
+    }
+}
+
+/// Finds the option of a key, which is its long name with either dashes or underscores
+fn find_option<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let name = key.replace('_', "-");
+    command.get_arguments()
+        .filter(|option| option.get_id() != CONFIG_ARG)
+        .filter(|option| takes_value(option) || matches!(option.get_action(), ArgAction::SetTrue))
+        .find(|option| option.get_long() == Some(name.as_str()))
+}
+
+/// Replaces "${NAME}" and "${NAME:-default}" with the environment variable, "$$" with "$"
+fn interpolate(value: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(variable) = rest.strip_prefix("${") {
+            let end = variable.find('}').ok_or(format!("Missing }} after {rest}"))?;
+            let (name, default) = match variable[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&variable[..end], None)
+            };
+            match (std::env::var(name), default) {
+                (Ok(value), _) => result.push_str(&value),
+                (Err(_), Some(default)) => result.push_str(default),
+                (Err(_), None) => return Err(format!("The environment variable {name} is not set")),
+            }
+            rest = &variable[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Checks the value the way the command line would, with an option which stands on its own,
+/// keeping the first lines of the clap message
+fn validate(option: &Arg, value: &str) -> Result<(), String> {
+    let long = option.get_long().expect("Settings are long options");
+    let check = option.clone()
+        .required(false)
+        .requires(Resettable::Reset)
+        .required_unless_present(Resettable::Reset)
+        .conflicts_with(Resettable::Reset);
+    Command::new(CONFIG_ARG)
+        .no_binary_name(true)
+        .arg(check)
+        .try_get_matches_from([format!("--{long}={value}")])
+        .map_err(|e| e.to_string().lines()
+            .take_while(|line| !line.is_empty())
+            .map(|line| line.trim_start_matches("error: ").trim())
+            .collect::<Vec<_>>()
+            .join(" "))?;
+    Ok(())
+}
+
+/// The settings of the server, or of one of its virtual hosts
+struct ConfigSeed<'a> {
+    run: &'a Command,
+    virtual_host: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for ConfigSeed<'_> {
+    type Value = ConfigFile;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<ConfigFile, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigSeed<'_> {
+    type Value = ConfigFile;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table of settings")
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<ConfigFile, E> {
+        Ok(ConfigFile::default())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ConfigFile, A::Error> {
+        let mut config_file = ConfigFile::default();
+        while let Some(key) = map.next_key::<String>()? {
+            if key == AUTH_TABLE {
+                let (auth_mode, auth) = map.next_value_seed(AuthSeed { run: self.run })?;
+                config_file.auth_mode = Some(auth_mode);
+                config_file.auth = auth;
+                continue;
+            }
+            if key == VHOST_TABLE && !self.virtual_host {
+                config_file.virtual_hosts = map.next_value_seed(VirtualHostsSeed { run: self.run })?;
+                continue;
+            }
+            let option = find_option(self.run, &key).ok_or_else(|| A::Error::custom(format!("Unknown setting {key}")))?;
+            if self.virtual_host && SERVER_SETTINGS.contains(&option.get_id().as_str()) {
+                return Err(A::Error::custom(format!("{key} applies to the whole server, not to a virtual host")));
+            }
+            config_file.run.push(map.next_value_seed(SettingSeed { option })?);
+        }
+        if self.virtual_host && !config_file.run.iter().any(|setting| setting.id == SERVER_NAMES && !setting.options.is_empty()) {
+            return Err(A::Error::custom("A virtual host needs its server_names"));
+        }
+        Ok(config_file)
+    }
+}
+
+/// The list of virtual host tables
+struct VirtualHostsSeed<'a> {
+    run: &'a Command,
+}
+
+impl<'de> DeserializeSeed<'de> for VirtualHostsSeed<'_> {
+    type Value = Vec<ConfigFile>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for VirtualHostsSeed<'_> {
+    type Value = Vec<ConfigFile>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of virtual host tables")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut virtual_hosts = vec![];
+        while let Some(virtual_host) = seq.next_element_seed(ConfigSeed { run: self.run, virtual_host: true })? {
+            virtual_hosts.push(virtual_host);
+        }
+        Ok(virtual_hosts)
+    }
+}
+
+/// The auth table, whose mode names the authentication subcommand the other keys belong to
+struct AuthSeed<'a> {
+    run: &'a Command,
+}
+
+impl<'de> DeserializeSeed<'de> for AuthSeed<'_> {
+    type Value = (String, Vec<Setting>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for AuthSeed<'_> {
+    type Value = (String, Vec<Setting>);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table with the auth mode and its settings")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let modes = self.run.get_subcommands().map(|mode| mode.get_name().to_string()).collect::<Vec<_>>();
+        let mut auth_mode: Option<String> = None;
+        let mut settings = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            if key == AUTH_MODE {
+                let mode = map.next_value::<String>()?;
+                if !modes.contains(&mode) {
+                    return Err(A::Error::custom(format!("Unknown auth mode {mode}, use one of {}", modes.join(", "))));
+                }
+                auth_mode = Some(mode);
+                continue;
+            }
+            // Until the mode is known the value is checked against the first mode having the setting
+            let mode_command = auth_mode.as_ref()
+                .and_then(|mode| self.run.find_subcommand(mode))
+                .into_iter()
+                .chain(self.run.get_subcommands())
+                .find(|mode_command| find_option(mode_command, &key).is_some())
+                .ok_or_else(|| A::Error::custom(format!("Unknown auth setting {key}")))?;
+            let option = find_option(mode_command, &key).expect("The mode has the setting");
+            settings.push((key, map.next_value_seed(SettingSeed { option })?));
+        }
+        let auth_mode = auth_mode.ok_or_else(|| A::Error::custom(format!("The auth table needs a mode, one of {}", modes.join(", "))))?;
+        let mode_command = self.run.find_subcommand(&auth_mode).expect("The mode was checked");
+        if let Some((key, _)) = settings.iter().find(|(key, _)| find_option(mode_command, key).is_none()) {
+            return Err(A::Error::custom(format!("{key} is no setting of the {auth_mode} mode")));
+        }
+        Ok((auth_mode, settings.into_iter().map(|(_, setting)| setting).collect()))
+    }
+}
+
+/// The value of one option: a scalar, a list for repeatable options, or a rule table
+struct SettingSeed<'a> {
+    option: &'a Arg,
+}
+
+impl SettingSeed<'_> {
+    fn setting<E: Error>(&self, values: Vec<String>) -> Result<Setting, E> {
+        let long = self.option.get_long().expect("Settings are long options");
+        if !matches!(self.option.get_action(), ArgAction::Append) && values.len() > 1 {
+            return Err(E::custom(format!("{long} takes a single value")));
+        }
+        for value in &values {
+            validate(self.option, value).map_err(E::custom)?;
+        }
+        Ok(Setting {
+            id: self.option.get_id().to_string(),
+            options: values.iter().map(|value| format!("--{long}={value}")).collect(),
+        })
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for SettingSeed<'_> {
+    type Value = Setting;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Setting, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SettingSeed<'_> {
+    type Value = Setting;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.option.get_action() {
+            ArgAction::SetTrue => formatter.write_str("true or false"),
+            ArgAction::Append => formatter.write_str("a value, a list of values or a rule table"),
+            _ => formatter.write_str("a value"),
+        }
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<Setting, E> {
+        if !matches!(self.option.get_action(), ArgAction::SetTrue) {
+            return self.setting(vec![value.to_string()]);
+        }
+        let long = self.option.get_long().expect("Settings are long options");
+        let options = if value { vec![format!("--{long}")] } else { vec![] };
+        Ok(Setting { id: self.option.get_id().to_string(), options })
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<Setting, E> {
+        self.setting(vec![value.to_string()])
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<Setting, E> {
+        self.setting(vec![value.to_string()])
+    }
+
+    fn visit_f64<E: Error>(self, value: f64) -> Result<Setting, E> {
+        self.setting(vec![value.to_string()])
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<Setting, E> {
+        self.setting(vec![interpolate(value).map_err(E::custom)?])
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Setting, E> {
+        Ok(Setting { id: self.option.get_id().to_string(), options: vec![] })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Setting, A::Error> {
+        let mut values = vec![];
+        while let Some(value) = seq.next_element_seed(ElementSeed { rule_table: true })? {
+            values.push(value);
+        }
+        if self.option.get_value_delimiter().is_some() && !matches!(self.option.get_action(), ArgAction::Append) {
+            let delimiter = self.option.get_value_delimiter().unwrap_or(',').to_string();
+            return self.setting(vec![values.join(&delimiter)]);
+        }
+        self.setting(values)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Setting, A::Error> {
+        let rule = ElementSeed { rule_table: true }.visit_map(map)?;
+        self.setting(vec![rule])
+    }
+}
+
+/// An element of a list, a scalar or, where rules are expected, a rule table
+#[derive(Clone, Copy)]
+struct ElementSeed {
+    rule_table: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for ElementSeed {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ElementSeed {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.rule_table {
+            true => formatter.write_str("a value or a rule table"),
+            false => formatter.write_str("a value"),
+        }
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_f64<E: Error>(self, value: f64) -> Result<String, E> {
+        Ok(value.to_string())
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<String, E> {
+        interpolate(value).map_err(E::custom)
+    }
+
+    /// A rule table like { path = "/api", origins = ["https://example.com"], credentials = true } is written
+    /// as "/api;origins=https://example.com;credentials", keeping the order of its keys
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<String, A::Error> {
+        if !self.rule_table {
+            return Err(A::Error::custom("Expected a value, not a table"));
+        }
+        let mut path = None;
+        let mut parts = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            let key = key.replace('_', "-");
+            match map.next_value_seed(RuleValueSeed)? {
+                RuleValue::Value(value) if key == RULE_PATH => path = Some(value),
+                _ if key == RULE_PATH => return Err(A::Error::custom("The path of a rule has to be a string")),
+                RuleValue::Value(value) => parts.push(format!("{key}={value}")),
+                RuleValue::Flag(true) => parts.push(key),
+                RuleValue::Flag(false) => {}
+            }
+        }
+        let path = path.ok_or_else(|| A::Error::custom("A rule table needs a path"))?;
+        Ok([path].into_iter().chain(parts).collect::<Vec<_>>().join(";"))
+    }
+}
+
+enum RuleValue {
+    Value(String),
+    /// true writes the key without value, false leaves it out
+    Flag(bool),
+}
+
+/// A value of a rule table, where lists are written comma separated
+struct RuleValueSeed;
+
+impl<'de> DeserializeSeed<'de> for RuleValueSeed {
+    type Value = RuleValue;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<RuleValue, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for RuleValueSeed {
+    type Value = RuleValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value, a list of values or true for a flag")
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<RuleValue, E> {
+        Ok(RuleValue::Flag(value))
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<RuleValue, E> {
+        Ok(RuleValue::Value(value.to_string()))
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<RuleValue, E> {
+        Ok(RuleValue::Value(value.to_string()))
+    }
+
+    fn visit_f64<E: Error>(self, value: f64) -> Result<RuleValue, E> {
+        Ok(RuleValue::Value(value.to_string()))
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<RuleValue, E> {
+        interpolate(value).map(RuleValue::Value).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RuleValue, A::Error> {
+        let mut values = vec![];
+        while let Some(value) = seq.next_element_seed(ElementSeed { rule_table: false })? {
+            values.push(value);
+        }
+        Ok(RuleValue::Value(values.join(",")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str, format: Format) -> Result<ConfigFile, String> {
+        let mut command = HttpServerArgs::command();
+        command.build();
+        parse_config_file(command.find_subcommand(RUN_COMMAND).unwrap(), content, format)
+    }
+
+    fn options(settings: &[Setting]) -> Vec<&str> {
+        settings.iter().flat_map(|setting| setting.options.iter().map(String::as_str)).collect()
+    }
+
+    #[test]
+    fn when_toml_file_should_produce_long_options() {
+        let config_file = parse(r#"
+            port = 8080
+            root-folder = "/srv/www"
+            etag = "weak"
+            compression = ["gzip", "br"]
+            header = ["X-Frame-Options: DENY"]
+            no_compression = false
+
+            [[cors]]
+            path = "/api"
+            origins = ["https://a.example", "https://b.example"]
+            credentials = true
+            max_age = 600
+
+            [auth]
+            mode = "basic"
+            username = "admin"
+            password = "$$ecret"
+            acl = [{ path = "/reports", users = ["alice"] }, "/reports/public;public"]
+        "#, Format::Toml).unwrap();
+        assert_eq!(options(&config_file.run), [
+            "--port=8080",
+            "--root-folder=/srv/www",
+            "--etag=weak",
+            "--compression=gzip", "--compression=br",
+            "--header=X-Frame-Options: DENY",
+            "--cors=/api;origins=https://a.example,https://b.example;credentials;max-age=600",
+        ]);
+        assert_eq!(config_file.auth_mode.as_deref(), Some("basic"));
+        assert_eq!(options(&config_file.auth), [
+            "--username=admin",
+            "--password=$ecret",
+            "--acl=/reports;users=alice",
+            "--acl=/reports/public;public",
+        ]);
+    }
+
+    #[test]
+    fn when_yaml_file_should_produce_the_same_options() {
+        let config_file = parse("
+port: 8080
+compression: [gzip, br]
+ip-rule:
+  - path: /data
+    allow: [127.0.0.0/8, '::1']
+    satisfy: any
+auth:
+  mode: basic
+  username: admin
+  password: s3cret
+", Format::Yaml).unwrap();
+        assert_eq!(options(&config_file.run), [
+            "--port=8080",
+            "--compression=gzip", "--compression=br",
+            "--ip-rule=/data;allow=127.0.0.0/8,::1;satisfy=any",
+        ]);
+        assert_eq!(options(&config_file.auth), ["--username=admin", "--password=s3cret"]);
+    }
+
+    #[test]
+    fn when_setting_is_invalid_should_report_its_line() {
+        let error = parse("port = 80\nsymlinks = \"sometimes\"\n", Format::Toml).unwrap_err();
+        assert!(error.contains("line 2"), "{error}");
+        assert!(error.contains("'sometimes'"), "{error}");
+        let error = parse("port: 80\nport_typo: 1\n", Format::Yaml).unwrap_err();
+        assert!(error.contains("Unknown setting port_typo"), "{error}");
+        let error = parse("port = [80, 81]\n", Format::Toml).unwrap_err();
+        assert!(error.contains("port takes a single value"), "{error}");
+        let error = parse("port = 80\n[auth]\nmode = \"basic\"\njwks = \"keys.json\"\n", Format::Toml).unwrap_err();
+        assert!(error.contains("jwks is no setting of the basic mode"), "{error}");
+        let error = parse("[auth]\nmode = \"kerberos\"\n", Format::Toml).unwrap_err();
+        assert!(error.contains("Unknown auth mode kerberos"), "{error}");
+    }
+
+    #[test]
+    fn when_interpolate_should_replace_environment_variables() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(interpolate("${PATH}").unwrap(), path);
+        assert_eq!(interpolate("a-${HTTP_SERVER_UNSET_VARIABLE:-default}-b").unwrap(), "a-default-b");
+        assert_eq!(interpolate("$$1 and $2").unwrap(), "$1 and $2");
+        assert!(interpolate("${HTTP_SERVER_UNSET_VARIABLE}").unwrap_err().contains("HTTP_SERVER_UNSET_VARIABLE"));
+        assert!(interpolate("${PATH").is_err());
+    }
+
+    #[test]
+    fn when_command_line_sets_an_option_should_replace_the_file_value() {
+        let path = std::env::temp_dir().join(format!("http_server_config_{}.toml", std::process::id()));
+        fs::write(&path, "port = 8080\nheader = [\"X-A: 1\", \"X-B: 2\"]\npool_size = 8\n[auth]\nmode = \"basic\"\nusername = \"admin\"\npassword = \"file\"\n").unwrap();
+        let args = ["http_server", "run", "--config", path.to_str().unwrap(), "--port", "9090", "--header=X-C: 3", "basic", "--password", "cli"];
+        let merged = merge_config_file(args.iter().map(OsString::from).collect()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let merged = merged.args.iter().map(|arg| arg.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(merged, [
+            "http_server", "run", "--pool-size=8",
+            "--config", path.to_str().unwrap(), "--port", "9090", "--header=X-C: 3",
+            "basic", "--username=admin", "--password", "cli",
+        ]);
+    }
+
+    #[test]
+    fn when_virtual_host_should_replace_settings_of_the_server() {
+        let path = std::env::temp_dir().join(format!("http_server_vhosts_{}.toml", std::process::id()));
+        fs::write(&path, "port = 8080\nroot_folder = \"/srv/default\"\nheader = [\"X-A: 1\"]\n\
+            [auth]\nmode = \"basic\"\nprotected_folders = \"/admin\"\n\
+            [[vhost]]\nserver_names = [\"docs.example.com\"]\nroot_folder = \"/srv/docs\"\n\
+            [[vhost]]\nserver_names = \"wiki.example.com\"\nheader = []\n[vhost.auth]\nmode = \"none\"\n").unwrap();
+        let args = ["http_server", "run", "--config", path.to_str().unwrap(), "--host", "0.0.0.0", "--root-folder", "/srv/cli"];
+        let merged = merge_config_file(args.iter().map(OsString::from).collect()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let virtual_hosts = merged.virtual_hosts.iter()
+            .map(|args| args.iter().map(|arg| arg.to_str().unwrap()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(virtual_hosts[0], [
+            "http_server", "run", "--server-names=docs.example.com", "--root-folder=/srv/docs",
+            "--port=8080", "--header=X-A: 1", "--config", path.to_str().unwrap(), "--host", "0.0.0.0",
+            "basic", "--protected-folders=/admin",
+        ]);
+        assert_eq!(virtual_hosts[1], [
+            "http_server", "run", "--server-names=wiki.example.com",
+            "--port=8080", "--config", path.to_str().unwrap(), "--host", "0.0.0.0", "--root-folder", "/srv/cli",
+            "none",
+        ]);
+    }
+
+    #[test]
+    fn when_virtual_host_is_invalid_should_report_it() {
+        let error = parse("port = 80\n[[vhost]]\nroot_folder = \"/srv/docs\"\n", Format::Toml).unwrap_err();
+        assert!(error.contains("A virtual host needs its server_names"), "{error}");
+        let error = parse("port = 80\n[[vhost]]\nserver_names = \"docs\"\nport = 81\n", Format::Toml).unwrap_err();
+        assert!(error.contains("port applies to the whole server"), "{error}");
+        let error = parse("vhost:\n  - server_names: [docs, 'bad name']\n", Format::Yaml).unwrap_err();
+        assert!(error.contains("Invalid server name bad name"), "{error}");
+    }
+}
@@ -0,0 +1,270 @@
+use fancy_regex::Regex;
+use linked_hash_set::LinkedHashSet;
+
+use crate::header_parser::HeaderMap;
+use crate::string_operations::matches_path_prefix;
+
+const DEFAULT_METHODS: [&str; 2] = ["GET", "HEAD"];
+
+/// An allowed origin: "*", an exact origin, a pattern like "https://*.example.com" or a regex prefixed with "~" matching the whole origin
+#[derive(Debug, Clone)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Result<OriginPattern, String> {
+        if pattern == "*" {
+            Ok(OriginPattern::Any)
+        } else if let Some(regex) = pattern.strip_prefix('~') {
+            // Anchored, as an origin merely containing a match, like https://x.example.com.evil.org, is another site
+            Regex::new(&format!("^(?:{regex})$"))
+                .map(OriginPattern::Regex)
+                .map_err(|e| format!("Invalid origin regex {regex}: {e}"))
+        } else if pattern.contains('*') {
+            Ok(OriginPattern::Wildcard(pattern.to_ascii_lowercase()))
+        } else {
+            Ok(OriginPattern::Exact(pattern.to_ascii_lowercase()))
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(exact) => origin.eq_ignore_ascii_case(exact),
+            OriginPattern::Wildcard(wildcard) => wildcard_match(wildcard, &origin.to_ascii_lowercase()),
+            OriginPattern::Regex(regex) => regex.is_match(origin).unwrap_or(false),
+        }
+    }
+}
+
+/// CORS policy of the resources below a path prefix
+#[derive(Debug, Clone)]
+pub struct CorsRule {
+    pub path_prefix: String,
+    pub origins: Vec<OriginPattern>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+/// Parses a rule like "/api;origins=https://app.example.com,https://*.example.com;methods=GET,HEAD;
+/// headers=Authorization;expose=ETag;credentials;max-age=600". Only the prefix and the origins are required.
+pub fn parse_cors_rule(rule: &str) -> Result<CorsRule, String> {
+    let mut parts = rule.split(';').map(|part| part.trim());
+    let path_prefix = parts.next().filter(|prefix| prefix.starts_with('/'))
+        .ok_or_else(|| format!("CORS rule {rule} has to start with a path prefix"))?;
+    let mut cors_rule = CorsRule {
+        path_prefix: path_prefix.to_string(),
+        origins: vec![],
+        methods: DEFAULT_METHODS.iter().map(|method| method.to_string()).collect(),
+        headers: vec![],
+        expose_headers: vec![],
+        credentials: false,
+        max_age: None,
+    };
+    for part in parts.filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        let values = || value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "origins" => cors_rule.origins = values().iter()
+                .map(|origin| OriginPattern::parse(origin))
+                .collect::<Result<_, _>>()?,
+            "methods" => cors_rule.methods = values().iter().map(|method| method.to_ascii_uppercase()).collect(),
+            "headers" => cors_rule.headers = values(),
+            "expose" => cors_rule.expose_headers = values(),
+            "credentials" => cors_rule.credentials = value.is_empty() || value.eq_ignore_ascii_case("true"),
+            "max-age" => cors_rule.max_age = Some(value.parse().map_err(|_| format!("Invalid CORS max-age {value}"))?),
+            _ => return Err(format!("Unknown CORS setting {key}"))
+        }
+    }
+    if cors_rule.origins.is_empty() {
+        return Err(format!("CORS rule {rule} has no origins"));
+    }
+    Ok(cors_rule)
+}
+
+/// The rule with the longest prefix matching the path
+pub(crate) fn find_cors_rule<'a>(path: &str, rules: &'a [CorsRule]) -> Option<&'a CorsRule> {
+    rules.iter()
+        .filter(|rule| matches_path_prefix(path, &rule.path_prefix))
+        .max_by_key(|rule| rule.path_prefix.len())
+}
+
+/// A preflight request is an OPTIONS request announcing the method of the actual request
+pub(crate) fn is_preflight(headers: &HeaderMap) -> bool {
+    headers.contains("Origin") && headers.contains("Access-Control-Request-Method")
+}
+
+/// The headers added to actual responses. Vary: Origin is always sent, as the response depends on the Origin.
+pub(crate) fn generate_cors_headers(rule: &CorsRule, headers: &HeaderMap) -> LinkedHashSet<String> {
+    let mut cors_headers = LinkedHashSet::new();
+    if let Some(allow_origin) = allowed_origin(rule, headers) {
+        cors_headers.insert(allow_origin);
+        if rule.credentials {
+            cors_headers.insert("Access-Control-Allow-Credentials: true\r\n".to_string());
+        }
+        if !rule.expose_headers.is_empty() {
+            cors_headers.insert(format!("Access-Control-Expose-Headers: {}\r\n", rule.expose_headers.join(", ")));
+        }
+    }
+    cors_headers.insert("Vary: Origin\r\n".to_string());
+    cors_headers
+}
+
+/// The headers answering a preflight request. Without Access-Control-Allow-Origin the browser refuses
+/// the actual request, so a disallowed origin, method or header only gets the Vary headers.
+pub(crate) fn generate_preflight_headers(rule: &CorsRule, headers: &HeaderMap) -> LinkedHashSet<String> {
+    let mut preflight_headers = LinkedHashSet::new();
+    let method = headers.get("Access-Control-Request-Method").unwrap_or("").trim();
+    let requested_headers: Vec<&str> = headers.get_all("Access-Control-Request-Headers").iter()
+        .flat_map(|value| value.split(','))
+        .map(|header| header.trim())
+        .filter(|header| !header.is_empty())
+        .collect();
+    let method_allowed = rule.methods.iter().any(|allowed| allowed == method);
+    let any_header = rule.headers.iter().any(|allowed| allowed == "*") && !rule.credentials;
+    let headers_allowed = any_header || requested_headers.iter()
+        .all(|requested| rule.headers.iter().any(|allowed| allowed.eq_ignore_ascii_case(requested)));
+    match allowed_origin(rule, headers) {
+        Some(allow_origin) if method_allowed && headers_allowed => {
+            preflight_headers.insert(allow_origin);
+            if rule.credentials {
+                preflight_headers.insert("Access-Control-Allow-Credentials: true\r\n".to_string());
+            }
+            preflight_headers.insert(format!("Access-Control-Allow-Methods: {}\r\n", rule.methods.join(", ")));
+            if !requested_headers.is_empty() {
+                let allowed_headers = if any_header { requested_headers.join(", ") } else { rule.headers.join(", ") };
+                preflight_headers.insert(format!("Access-Control-Allow-Headers: {allowed_headers}\r\n"));
+            }
+            if let Some(max_age) = rule.max_age {
+                preflight_headers.insert(format!("Access-Control-Max-Age: {max_age}\r\n"));
+            }
+        }
+        _ => println!("CORS preflight refused for {:?} {method} {:?}", headers.get("Origin"), requested_headers)
+    }
+    preflight_headers.insert("Vary: Origin, Access-Control-Request-Method, Access-Control-Request-Headers\r\n".to_string());
+    preflight_headers
+}
+
+/// Credentials cannot be combined with "*", so the origin is echoed whenever credentials are allowed
+fn allowed_origin(rule: &CorsRule, headers: &HeaderMap) -> Option<String> {
+    let origin = headers.get("Origin")?.trim();
+    let pattern = rule.origins.iter().find(|pattern| pattern.matches(origin))?;
+    let allow_origin = match pattern {
+        OriginPattern::Any if !rule.credentials => "*",
+        _ => origin
+    };
+    Some(format!("Access-Control-Allow-Origin: {allow_origin}\r\n"))
+}
+
+/// Matches a pattern where "*" stands for any sequence of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false
+    };
+    let pieces: Vec<&str> = pieces.collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i == pieces.len() - 1 {
+            return rest.ends_with(piece);
+        }
+        match rest.find(piece) {
+            Some(position) => rest = &rest[position + piece.len()..],
+            None => return false
+        }
+    }
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header_parser::message_headers;
+
+    use super::*;
+
+    fn headers(header_str: &str) -> HeaderMap {
+        let (_, header_map) = message_headers(format!("{header_str}\r\n").as_bytes()).unwrap();
+        header_map
+    }
+
+    #[test]
+    fn when_parse_cors_rule_should_read_all_settings() {
+        let rule = parse_cors_rule("/api;origins=https://app.example.com,https://*.example.com,~^http://localhost:\\d+$;\
+methods=get,head,post;headers=Authorization,Content-Type;expose=ETag;credentials;max-age=600").unwrap();
+        assert_eq!(rule.path_prefix, "/api");
+        assert_eq!(rule.origins.len(), 3);
+        assert_eq!(rule.methods, vec!["GET", "HEAD", "POST"]);
+        assert_eq!(rule.headers, vec!["Authorization", "Content-Type"]);
+        assert_eq!(rule.expose_headers, vec!["ETag"]);
+        assert!(rule.credentials);
+        assert_eq!(rule.max_age, Some(600));
+        assert!(parse_cors_rule("api;origins=*").is_err());
+        assert!(parse_cors_rule("/api").is_err());
+        assert!(parse_cors_rule("/api;origins=*;colour=blue").is_err());
+    }
+
+    #[test]
+    fn when_origin_pattern_should_match_exact_wildcard_and_regex() {
+        let exact = OriginPattern::parse("https://App.example.com").unwrap();
+        assert!(exact.matches("https://app.example.com"));
+        assert!(!exact.matches("https://app.example.com.evil.org"));
+        let wildcard = OriginPattern::parse("https://*.example.com").unwrap();
+        assert!(wildcard.matches("https://cdn.eu.example.com"));
+        assert!(!wildcard.matches("https://example.com.evil.org"));
+        assert!(!wildcard.matches("http://cdn.example.com"));
+        let regex = OriginPattern::parse("~^http://localhost:\\d+$").unwrap();
+        assert!(regex.matches("http://localhost:3000"));
+        assert!(!regex.matches("http://localhost"));
+        let unanchored = OriginPattern::parse("~https://.*\\.example\\.com").unwrap();
+        assert!(unanchored.matches("https://x.example.com"));
+        assert!(!unanchored.matches("https://x.example.com.evil.com"));
+        assert!(!unanchored.matches("http://https://x.example.com"));
+    }
+
+    #[test]
+    fn when_find_cors_rule_should_prefer_longest_prefix() {
+        let rules = vec![parse_cors_rule("/;origins=*").unwrap(), parse_cors_rule("/api;origins=*").unwrap()];
+        assert_eq!(find_cors_rule("/api/users.json", &rules).unwrap().path_prefix, "/api");
+        assert_eq!(find_cors_rule("/apidocs.html", &rules).unwrap().path_prefix, "/");
+        assert!(find_cors_rule("/api", &rules[1..]).is_some());
+        assert!(find_cors_rule("/fonts/a.woff", &rules[1..]).is_none());
+    }
+
+    #[test]
+    fn when_generate_cors_headers_should_echo_origin_with_credentials() {
+        let rule = parse_cors_rule("/;origins=*;credentials;expose=ETag,Content-Length").unwrap();
+        let cors_headers = generate_cors_headers(&rule, &headers("Origin: https://a.test\r\n"));
+        assert!(cors_headers.contains("Access-Control-Allow-Origin: https://a.test\r\n"));
+        assert!(cors_headers.contains("Access-Control-Allow-Credentials: true\r\n"));
+        assert!(cors_headers.contains("Access-Control-Expose-Headers: ETag, Content-Length\r\n"));
+
+        let public = parse_cors_rule("/;origins=*").unwrap();
+        let cors_headers = generate_cors_headers(&public, &headers("Origin: https://a.test\r\n"));
+        assert!(cors_headers.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(cors_headers.contains("Vary: Origin\r\n"));
+    }
+
+    #[test]
+    fn when_generate_preflight_headers_should_check_method_and_headers() {
+        let rule = parse_cors_rule("/;origins=https://a.test;methods=GET,PUT;headers=X-Token;max-age=60").unwrap();
+        let allowed = headers("Origin: https://a.test\r\nAccess-Control-Request-Method: PUT\r\nAccess-Control-Request-Headers: x-token\r\n");
+        let preflight_headers = generate_preflight_headers(&rule, &allowed);
+        assert!(preflight_headers.contains("Access-Control-Allow-Origin: https://a.test\r\n"));
+        assert!(preflight_headers.contains("Access-Control-Allow-Methods: GET, PUT\r\n"));
+        assert!(preflight_headers.contains("Access-Control-Allow-Headers: X-Token\r\n"));
+        assert!(preflight_headers.contains("Access-Control-Max-Age: 60\r\n"));
+
+        let refused = headers("Origin: https://a.test\r\nAccess-Control-Request-Method: DELETE\r\n");
+        assert!(!generate_preflight_headers(&rule, &refused).iter().any(|header| header.starts_with("Access-Control-Allow")));
+        let other_origin = headers("Origin: https://b.test\r\nAccess-Control-Request-Method: GET\r\n");
+        assert!(!generate_preflight_headers(&rule, &other_origin).iter().any(|header| header.starts_with("Access-Control-Allow")));
+    }
+}
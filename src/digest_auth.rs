@@ -0,0 +1,263 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::args::{DigestAlgorithm, DigestAuthCommand};
+use crate::htpasswd::htdigest_ha1s;
+use crate::http_parser::{DigestAuthentication, Uri};
+use crate::string_operations::escape_quoted;
+
+lazy_static! {
+    static ref NONCES: Mutex<NonceTable> = Mutex::new(NonceTable::default());
+    static ref NONCE_SECRET: RandomState = RandomState::new();
+}
+
+static NONCE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Above this number of unexpired nonces the oldest ones are forgotten, so unauthenticated requests can't exhaust memory
+const MAX_NONCES: usize = 10_000;
+
+/// Issued nonces with the time they expire and the highest nonce count seen so far,
+/// and the nonces in the order they were issued. Nonces are only ever removed from the front.
+#[derive(Default)]
+struct NonceTable {
+    nonces: HashMap<String, (Instant, u32)>,
+    order: VecDeque<String>,
+}
+
+impl NonceTable {
+    fn insert(&mut self, nonce: String, expires: Instant, now: Instant, max_nonces: usize) {
+        // Checking only the oldest nonces keeps this cheap, a later one expiring first is forgotten when it gets in front
+        while let Some(oldest) = self.order.front() {
+            let expired = self.nonces.get(oldest).is_none_or(|(oldest_expires, _)| *oldest_expires <= now);
+            if !expired && self.nonces.len() < max_nonces {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.nonces.remove(&oldest);
+            }
+        }
+        self.order.push_back(nonce.clone());
+        self.nonces.insert(nonce, (expires, 0));
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum DigestVerification {
+    /// The response matches, carrying the authenticated user name
+    Valid(String),
+    /// The response matches, but the nonce expired or is unknown, so the client should retry with a fresh one
+    Stale,
+    Invalid,
+}
+
+impl DigestAlgorithm {
+    pub(crate) fn token(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Md5 => "MD5",
+        }
+    }
+
+    fn hash(&self, data: &str) -> String {
+        let digest = match self {
+            DigestAlgorithm::Sha256 => Sha256::digest(data.as_bytes()).to_vec(),
+            DigestAlgorithm::Md5 => Md5::digest(data.as_bytes()).to_vec(),
+        };
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// A new nonce which cannot be guessed from earlier ones, valid for the lifetime of the issuing command
+fn issue_nonce(lifetime: Duration) -> String {
+    let mut hasher = NONCE_SECRET.build_hasher();
+    hasher.write_u64(NONCE_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0));
+    let nonce = DigestAlgorithm::Sha256.hash(&format!("{}:{}", hasher.finish(), std::process::id()));
+
+    let now = Instant::now();
+    NONCES.lock().unwrap().insert(nonce.clone(), now + lifetime, now, MAX_NONCES);
+    nonce
+}
+
+/// One WWW-Authenticate challenge per algorithm, all with the same fresh nonce (RFC 7616, section 3.3)
+pub(crate) fn generate_digest_challenges(digest_auth_command: &DigestAuthCommand, realm: &str, stale: bool) -> Vec<String> {
+    let nonce = issue_nonce(Duration::from_secs(digest_auth_command.nonce_lifetime));
+    let realm = escape_quoted(realm);
+    let stale = if stale { ", stale=true" } else { "" };
+    digest_auth_command.algorithms.iter()
+        .map(|algorithm| format!("Digest realm=\"{realm}\", qop=\"auth\", algorithm={}, nonce=\"{nonce}\"{stale}",
+                                 algorithm.token()))
+        .collect()
+}
+
+/// Checks the response of the client for the realm, the method and the request target. The nonce count has to
+/// grow with every request using the same nonce, so a captured request cannot be replayed.
+pub(crate) fn verify_digest(digest_auth_command: &DigestAuthCommand, digest: &DigestAuthentication,
+                            realm: &str, method: &str, request_uri: &Uri) -> DigestVerification {
+    let (Some(username), Some(nonce), Some(uri), Some(response), Some(nc), Some(cnonce)) = (
+        digest.param("username"), digest.param("nonce"), digest.param("uri"),
+        digest.param("response"), digest.param("nc"), digest.param("cnonce")
+    ) else {
+        return DigestVerification::Invalid;
+    };
+    let algorithm = match digest.param("algorithm").unwrap_or("MD5") {
+        token if token.eq_ignore_ascii_case("SHA-256") => DigestAlgorithm::Sha256,
+        token if token.eq_ignore_ascii_case("MD5") => DigestAlgorithm::Md5,
+        token => {
+            println!("Unsupported digest algorithm {token}");
+            return DigestVerification::Invalid;
+        }
+    };
+    let Ok(nonce_count) = u32::from_str_radix(nc, 16) else {
+        return DigestVerification::Invalid;
+    };
+    let same_target = Uri::parse(uri)
+        .map(|digest_uri| digest_uri.path == request_uri.path && digest_uri.query == request_uri.query)
+        .unwrap_or(false);
+    if !digest_auth_command.algorithms.contains(&algorithm) || digest.param("realm") != Some(realm)
+        || digest.param("qop") != Some("auth") || !same_target {
+        return DigestVerification::Invalid;
+    }
+
+    let ha2 = algorithm.hash(&format!("{method}:{uri}"));
+    let expected_response = |ha1: &str| algorithm.hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+    let matches = ha1s(digest_auth_command, algorithm, username, realm).iter()
+        .any(|ha1| bool::from(expected_response(ha1).as_bytes().ct_eq(response.to_ascii_lowercase().as_bytes())));
+    if !matches {
+        return DigestVerification::Invalid;
+    }
+
+    let mut nonces = NONCES.lock().unwrap();
+    match nonces.nonces.get_mut(nonce) {
+        Some((expires, _)) if Instant::now() >= *expires => DigestVerification::Stale,
+        Some((_, last_count)) if nonce_count > *last_count => {
+            *last_count = nonce_count;
+            DigestVerification::Valid(username.to_string())
+        }
+        Some(_) => {
+            println!("Replayed nonce count {nc} for user {username}");
+            DigestVerification::Invalid
+        }
+        None => DigestVerification::Stale
+    }
+}
+
+/// The H(username:realm:password) candidates of the user, from the htdigest file or the command line user
+fn ha1s(digest_auth_command: &DigestAuthCommand, algorithm: DigestAlgorithm, username: &str, realm: &str) -> Vec<String> {
+    match &digest_auth_command.htdigest {
+        Some(htdigest) => {
+            let hash_length = algorithm.hash("").len();
+            htdigest_ha1s(htdigest, username, realm).into_iter()
+                .filter(|ha1| ha1.len() == hash_length)
+                .collect()
+        }
+        None if username == digest_auth_command.username =>
+            vec![algorithm.hash(&format!("{username}:{realm}:{}", digest_auth_command.password))],
+        None => vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest_auth_factory(algorithms: Vec<DigestAlgorithm>) -> DigestAuthCommand {
+        DigestAuthCommand {
+            protected_folders: "/".to_string(),
+            htdigest: None,
+            htgroup: None,
+            acl: vec![],
+            algorithms,
+            nonce_lifetime: 300,
+            username: "Mufasa".to_string(),
+            password: "Circle of Life".to_string(),
+        }
+    }
+
+    fn respond(algorithm: DigestAlgorithm, password: &str, nonce: &str, nc: &str, uri: &str) -> DigestAuthentication {
+        let realm = "http-auth@example.org";
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let ha1 = algorithm.hash(&format!("Mufasa:{realm}:{password}"));
+        let ha2 = algorithm.hash(&format!("GET:{uri}"));
+        let response = algorithm.hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+        let params = [
+            ("username", "Mufasa"), ("realm", realm), ("nonce", nonce), ("uri", uri), ("algorithm", algorithm.token()),
+            ("response", &response), ("nc", nc), ("cnonce", cnonce), ("qop", "auth"),
+        ];
+        DigestAuthentication {
+            params: params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        }
+    }
+
+    #[test]
+    fn when_hash_should_match_rfc_7616_example() {
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let sha256 = respond(DigestAlgorithm::Sha256, "Circle of Life", nonce, "00000001", "/dir/index.html");
+        assert_eq!(sha256.param("response"), Some("753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"));
+        let md5 = respond(DigestAlgorithm::Md5, "Circle of Life", nonce, "00000001", "/dir/index.html");
+        assert_eq!(md5.param("response"), Some("8ca523f5e9506fed4657c9700eebdbec"));
+    }
+
+    #[test]
+    fn when_verify_digest_should_reject_replays_and_wrong_passwords() {
+        let command = digest_auth_factory(vec![DigestAlgorithm::Sha256, DigestAlgorithm::Md5]);
+        let challenges = generate_digest_challenges(&command, "http-auth@example.org", false);
+        assert_eq!(challenges.len(), 2);
+        assert!(challenges[0].contains("algorithm=SHA-256"));
+        let nonce = challenges[0].split("nonce=\"").nth(1).unwrap().trim_end_matches('"');
+        let request_uri = Uri::parse("/dir/index.html?a=1").unwrap();
+        let verify = |digest: &DigestAuthentication| verify_digest(&command, digest, "http-auth@example.org", "GET", &request_uri);
+
+        let first = respond(DigestAlgorithm::Sha256, "Circle of Life", nonce, "00000001", "/dir/index.html?a=1");
+        assert_eq!(verify(&first), DigestVerification::Valid("Mufasa".to_string()));
+        assert_eq!(verify(&first), DigestVerification::Invalid);
+        let second = respond(DigestAlgorithm::Md5, "Circle of Life", nonce, "00000002", "/dir/index.html?a=1");
+        assert_eq!(verify(&second), DigestVerification::Valid("Mufasa".to_string()));
+        let wrong = respond(DigestAlgorithm::Sha256, "Circle of Death", nonce, "00000003", "/dir/index.html?a=1");
+        assert_eq!(verify(&wrong), DigestVerification::Invalid);
+        let other_target = respond(DigestAlgorithm::Sha256, "Circle of Life", nonce, "00000004", "/dir/other.html");
+        assert_eq!(verify(&other_target), DigestVerification::Invalid);
+    }
+
+    #[test]
+    fn when_verify_digest_with_unknown_or_expired_nonce_should_be_stale() {
+        let command = digest_auth_factory(vec![DigestAlgorithm::Sha256]);
+        let request_uri = Uri::parse("/").unwrap();
+        let unknown = respond(DigestAlgorithm::Sha256, "Circle of Life", "unknown", "00000001", "/");
+        assert_eq!(verify_digest(&command, &unknown, "http-auth@example.org", "GET", &request_uri), DigestVerification::Stale);
+
+        let challenges = generate_digest_challenges(&command, "http-auth@example.org", false);
+        let nonce = challenges[0].split("nonce=\"").nth(1).unwrap().trim_end_matches('"');
+        NONCES.lock().unwrap().nonces.get_mut(nonce).unwrap().0 = Instant::now();
+        let expired = respond(DigestAlgorithm::Sha256, "Circle of Life", nonce, "00000001", "/");
+        assert_eq!(verify_digest(&command, &expired, "http-auth@example.org", "GET", &request_uri), DigestVerification::Stale);
+
+        let md5 = respond(DigestAlgorithm::Md5, "Circle of Life", nonce, "00000001", "/");
+        assert_eq!(verify_digest(&command, &md5, "http-auth@example.org", "GET", &request_uri), DigestVerification::Invalid);
+    }
+
+    #[test]
+    fn when_nonces_exceed_limit_should_forget_oldest() {
+        let now = Instant::now();
+        let mut table = NonceTable::default();
+        for nonce in ["nonce0", "nonce1", "nonce2", "nonce3"] {
+            table.insert(nonce.to_string(), now + Duration::from_secs(300), now, 2);
+        }
+        assert_eq!(table.order, vec!["nonce2".to_string(), "nonce3".to_string()]);
+        assert_eq!(table.nonces.len(), 2);
+
+        let later = now + Duration::from_secs(300);
+        table.insert("nonce4".to_string(), later + Duration::from_secs(300), later, 10);
+        assert_eq!(table.order, vec!["nonce4".to_string()]);
+        assert!(table.nonces.contains_key("nonce4"));
+    }
+}
@@ -1,12 +1,14 @@
+use std::fs;
 use std::io::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 use chrono::{Datelike, DateTime, Local, Utc};
 
-use crate::{remove_double_slash};
+use crate::args::SymlinkPolicy;
+use crate::remove_double_slash;
 
-const DEFAULT_FILE_NAME: &'static str = "unknown";
+const DEFAULT_FILE_NAME: &str = "unknown";
 
 struct FileData {
     file_name: String,
@@ -16,6 +18,34 @@ struct FileData {
     modified_date: String
 }
 
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum PathError {
+    /// The URI path contains characters which are never valid in a file name
+    BadRequest,
+    /// The path leaves the root folder or breaks the symlink policy
+    Forbidden,
+}
+
+/// Removes the dot segments (RFC 3986, section 5.2.4) of an already percent-decoded URI path,
+/// so that encoded dots and slashes cannot sneak past the dot segment removal.
+pub(crate) fn normalize_path(decoded: &str) -> Result<String, PathError> {
+    if decoded.contains('\0') || (cfg!(windows) && decoded.contains('\\')) {
+        return Err(PathError::BadRequest);
+    }
+    let mut segments: Vec<&str> = Vec::new();
+    let mut ends_with_slash = false;
+    for segment in decoded.split('/') {
+        ends_with_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => { segments.pop(); }
+            _ => segments.push(segment)
+        }
+    }
+    let normalized = format!("/{}", segments.join("/"));
+    Ok(if ends_with_slash && !segments.is_empty() { format!("{normalized}/") } else { normalized })
+}
+
 pub(crate) fn build_path(uri: String, root_folder: &String) -> String {
     let path_str = if root_folder.starts_with("/") { format!("/{}/{}", root_folder, uri) } else {
         format!("./{}/{}", root_folder, uri)
@@ -25,7 +55,7 @@ pub(crate) fn build_path(uri: String, root_folder: &String) -> String {
 
 pub(crate) fn is_folder(built_path: String) -> Option<PathBuf> {
     let path = PathBuf::from(built_path);
-    return if path.is_dir() { Some(path) } else { None };
+    if path.is_dir() { Some(path) } else { None }
 }
 
 pub(crate) fn list_folder(pb: PathBuf, root_folder: &String) -> String {
@@ -43,23 +73,15 @@ pub(crate) fn list_folder(pb: PathBuf, root_folder: &String) -> String {
         <h1>{folder_name}</h1>
 ");
     let mut files_vec = Vec::new();
-    for dir_res in files {
-        match dir_res {
-            Ok(dir_entry) => {
-                let f_name = dir_entry.file_name();
-                let file_name = f_name.to_str().unwrap_or(DEFAULT_FILE_NAME);
-                let file_data_option = adapt_file_data(&dir_entry.path(), file_name);
-                match file_data_option {
-                    Some(file_date) => { files_vec.push(file_date) }
-                    None => {}
-                }
-            }
-            Err(_) => {}
-        }
+    for dir_entry in files.flatten() {
+        let f_name = dir_entry.file_name();
+        let file_name = f_name.to_str().unwrap_or(DEFAULT_FILE_NAME);
+        let file_data_option = adapt_file_data(&dir_entry.path(), file_name);
+        if let Some(file_date) = file_data_option { files_vec.push(file_date) }
     }
     buffered += format!("<h4>total {}</h4>", files_vec.len()).as_str();
     buffered += "<table>";
-    files_vec.sort_by(|a, b| create_key(a).cmp(&create_key(b)));
+    files_vec.sort_by_key(create_key);
     for f in files_vec {
         buffered += print_table_row(folder_name, &f).as_str();
     }
@@ -70,77 +92,107 @@ pub(crate) fn list_folder(pb: PathBuf, root_folder: &String) -> String {
 
 fn create_key(file_data: &FileData) -> String {
     let marker = if file_data.is_dir { "d" } else { "f" };
-    return format!("{}_{}", marker, file_data.file_name);
+    format!("{}_{}", marker, file_data.file_name)
 }
 
 fn print_table_row(folder_name: &str, file_data: &FileData) -> String {
-    match file_data {
-        FileData { file_name, file_size, create_date, is_dir, modified_date } => {
-            let folder_char = if *is_dir { "&#x1F4C1;" } else { "&#128196;" };
-            format!("\
-                <tr>\
-                    <td>{folder_char}</td>\
-                    <td align='right'>{file_size}</td>\
-                    <td align='right'>{create_date}</td>\
-                    <td align='right'>{modified_date}</td>\
-                    <td><a href='{folder_name}/{file_name}'>{file_name}</a></td>\
-                </tr>")
-        }
-    }
+    let FileData { file_name, file_size, create_date, is_dir, modified_date } = file_data;
+    let folder_char = if *is_dir { "&#x1F4C1;" } else { "&#128196;" };
+    format!("\
+        <tr>\
+            <td>{folder_char}</td>\
+            <td align='right'>{file_size}</td>\
+            <td align='right'>{create_date}</td>\
+            <td align='right'>{modified_date}</td>\
+            <td><a href='{folder_name}/{file_name}'>{file_name}</a></td>\
+        </tr>")
 }
 
-fn adapt_file_data(path_buf: &PathBuf, file_name: &str) -> Option<FileData> {
+fn adapt_file_data(path_buf: &Path, file_name: &str) -> Option<FileData> {
     let metadata = path_buf.metadata().ok()?;
 
     let created_str = convert_time(metadata.created());
     let modified_str = convert_time(metadata.modified());
-    return Some(FileData {
+    Some(FileData {
         file_size: metadata.len(),
         file_name: file_name.to_string(),
         is_dir: path_buf.is_dir(),
         create_date: created_str,
         modified_date: modified_str
-    });
+    })
 }
 
 fn convert_time(created_res: Result<SystemTime, Error>) -> String {
     let mut created_str = "".to_string();
 
-    match created_res {
-        Ok(system_time) => {
-            let date_time: DateTime<Local> = system_time.into();
+    if let Ok(system_time) = created_res {
+        let date_time: DateTime<Local> = system_time.into();
 
-            let format = if date_time.year() == Utc::now().year() { "%b %e %T" } else { "%b %e %Y" };
-            created_str = date_time.format(format).to_string();
-        }
-        Err(_) => {}
+        let format = if date_time.year() == Utc::now().year() { "%b %e %T" } else { "%b %e %Y" };
+        created_str = date_time.format(format).to_string();
     }
     created_str
 }
 
-pub(crate) fn transform_uri(uri: String, root_folder: &String) -> String {
+/// Maps an already normalized URI path onto the root folder and checks that the result stays inside it
+pub(crate) fn transform_uri(uri: String, root_folder: &String, index: &[String], symlink_policy: &SymlinkPolicy) -> Result<String, PathError> {
     println!("uri: {uri} root_folder: {root_folder}");
     let path_str = build_path(uri, root_folder);
 
-    let path_buf = PathBuf::from(path_str);
+    let mut path_buf = PathBuf::from(path_str);
     if path_buf.is_dir() {
-        // Check if it has index.htm or index.html
-        let paths = vec!["index.html", "index.htm"];
-        for path in paths {
+        // Check if it has one of the index files, in their order
+        for path in index {
             let mut path_index_html = path_buf.clone();
             path_index_html.push(path);
             if path_index_html.exists() {
-                return path_index_html.to_str().unwrap().to_string();
+                path_buf = path_index_html;
+                break;
+            }
+        }
+    }
+    check_containment(&path_buf, root_folder, symlink_policy)?;
+    Ok(path_buf.to_str().unwrap().to_string())
+}
+
+/// Paths which do not exist pass, so that they produce a 404 later on
+pub(crate) fn check_containment(path: &Path, root_folder: &String, symlink_policy: &SymlinkPolicy) -> Result<(), PathError> {
+    match symlink_policy {
+        SymlinkPolicy::Follow => Ok(()),
+        SymlinkPolicy::FollowWithinRoot => {
+            match (fs::canonicalize(root_folder), fs::canonicalize(path)) {
+                (Ok(canonical_root), Ok(canonical_path)) if !canonical_path.starts_with(&canonical_root) => {
+                    println!("Path {:?} escapes root folder {:?}", canonical_path, canonical_root);
+                    Err(PathError::Forbidden)
+                }
+                _ => Ok(())
+            }
+        }
+        SymlinkPolicy::Deny => {
+            let root_path = PathBuf::from(build_path(String::new(), root_folder));
+            let mut current = path.to_path_buf();
+            while current != root_path {
+                if fs::symlink_metadata(&current).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false) {
+                    println!("Symbolic link {:?} denied", current);
+                    return Err(PathError::Forbidden);
+                }
+                if !current.pop() {
+                    break;
+                }
             }
+            Ok(())
         }
     }
-    return path_buf.to_str().unwrap().to_string();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn index_files() -> Vec<String> {
+        vec!["index.html".to_string(), "index.htm".to_string()]
+    }
+
     #[test]
     fn when_build_path_should_build_path() {
         let built = build_path(String::from("info.txt"), &String::from("root"));
@@ -151,38 +203,81 @@ mod tests {
     fn when_is_folder_should_be_folder() {
         let built_path = build_path(String::from("css"), &String::from("root"));
         let folder = is_folder(built_path);
-        assert_eq!(folder.is_some(), true);
+        assert!(folder.is_some());
     }
 
     #[test]
     fn when_is_folder_should_be_false() {
         let built_path = build_path(String::from("index.html"), &String::from("root"));
         let folder = is_folder(built_path);
-        assert_eq!(folder.is_some(), false);
+        assert!(folder.is_none());
     }
 
     #[test]
     fn when_does_not_exist_should_be_false() {
         let built_path = build_path(String::from("index1.html"), &String::from("root"));
         let folder = is_folder(built_path);
-        assert_eq!(folder.is_some(), false);
+        assert!(folder.is_none());
     }
 
     #[test]
     fn when_transform_uri_should_produce_index_html() {
-        let res = transform_uri("".to_string(), &String::from("root"));
-        assert_eq!(res.contains("index.html"), true);
+        let res = transform_uri("".to_string(), &String::from("root"), &index_files(), &SymlinkPolicy::FollowWithinRoot).unwrap();
+        assert!(res.contains("index.html"));
     }
 
     #[test]
     fn when_transform_uri_should_produce_index_htm() {
-        let res = transform_uri("".to_string(), &String::from("root/pdf"));
-        assert_eq!(res.contains("index.htm"), true);
+        let res = transform_uri("".to_string(), &String::from("root/pdf"), &index_files(), &SymlinkPolicy::FollowWithinRoot).unwrap();
+        assert!(res.contains("index.htm"));
+    }
+
+    #[test]
+    fn when_transform_uri_should_produce_first_configured_index() {
+        let index = vec!["default.htm".to_string(), "index.htm".to_string(), "index.html".to_string()];
+        let res = transform_uri("".to_string(), &String::from("root"), &index, &SymlinkPolicy::FollowWithinRoot).unwrap();
+        assert!(res.ends_with("index.html"));
+        let res = transform_uri("".to_string(), &String::from("root"), &[], &SymlinkPolicy::FollowWithinRoot).unwrap();
+        assert!(!res.contains("index"));
     }
 
     #[test]
     fn when_transform_uri_should_produce_folder() {
-        let res = transform_uri("".to_string(), &String::from("root/pdf/test"));
-        assert_eq!(res.contains("root/pdf/test"), true);
+        let res = transform_uri("".to_string(), &String::from("root/pdf/test"), &index_files(), &SymlinkPolicy::FollowWithinRoot).unwrap();
+        assert!(res.contains("root/pdf/test"));
+    }
+
+    #[test]
+    fn when_normalize_path_should_remove_dot_segments() {
+        assert_eq!(normalize_path("/a/b/c/./../../g"), Ok("/a/g".to_string()));
+        assert_eq!(normalize_path("/../../etc/passwd"), Ok("/etc/passwd".to_string()));
+        assert_eq!(normalize_path("/../..//etc/passwd"), Ok("/etc/passwd".to_string()));
+        assert_eq!(normalize_path("/css/"), Ok("/css/".to_string()));
+        assert_eq!(normalize_path("/css/.."), Ok("/".to_string()));
+    }
+
+    #[test]
+    fn when_normalize_path_invalid_should_be_bad_request() {
+        assert_eq!(normalize_path("/info.txt\0.html"), Err(PathError::BadRequest));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn when_symlink_escapes_root_should_be_forbidden() {
+        let root = std::env::temp_dir().join(format!("http_server_symlink_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let link = root.join("outside");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink("/etc", &link).unwrap();
+        let root_folder = root.to_str().unwrap().to_string();
+
+        let within_root = transform_uri("/outside/hostname".to_string(), &root_folder, &index_files(), &SymlinkPolicy::FollowWithinRoot);
+        let denied = transform_uri("/outside".to_string(), &root_folder, &index_files(), &SymlinkPolicy::Deny);
+        let followed = transform_uri("/outside".to_string(), &root_folder, &index_files(), &SymlinkPolicy::Follow);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(within_root, Err(PathError::Forbidden));
+        assert_eq!(denied, Err(PathError::Forbidden));
+        assert!(followed.is_ok());
     }
-}
\ No newline at end of file
+}
@@ -1,43 +1,107 @@
 use linked_hash_set::LinkedHashSet;
 
-pub const STATUS_OK: &'static str = "HTTP/1.1 200 OK";
-pub const STATUS_BAD_REQUEST: &'static str = "HTTP/1.1 400 Bad Request";
-pub const STATUS_NOT_FOUND: &'static str = "HTTP/1.1 404 Not Found";
-pub const STATUS_METHOD_NOT_ALLOWED: &'static str = "HTTP/1.1 405 Method Not Allowed";
+pub const STATUS_OK: &str = "HTTP/1.1 200 OK";
+pub const STATUS_PARTIAL_CONTENT: &str = "HTTP/1.1 206 Partial Content";
+pub const STATUS_MOVED_PERMANENTLY: &str = "HTTP/1.1 301 Moved Permanently";
+pub const STATUS_PERMANENT_REDIRECT: &str = "HTTP/1.1 308 Permanent Redirect";
+pub const STATUS_BAD_REQUEST: &str = "HTTP/1.1 400 Bad Request";
+pub const STATUS_FORBIDDEN: &str = "HTTP/1.1 403 Forbidden";
+pub const STATUS_NOT_FOUND: &str = "HTTP/1.1 404 Not Found";
+pub const STATUS_METHOD_NOT_ALLOWED: &str = "HTTP/1.1 405 Method Not Allowed";
+pub const STATUS_PRECONDITION_FAILED: &str = "HTTP/1.1 412 Precondition Failed";
+pub const STATUS_PAYLOAD_TOO_LARGE: &str = "HTTP/1.1 413 Payload Too Large";
+pub const STATUS_RANGE_NOT_SATISFIABLE: &str = "HTTP/1.1 416 Range Not Satisfiable";
+pub const STATUS_MISDIRECTED_REQUEST: &str = "HTTP/1.1 421 Misdirected Request";
+pub const STATUS_TOO_MANY_REQUESTS: &str = "HTTP/1.1 429 Too Many Requests";
+pub const STATUS_INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 Internal Server Error";
+pub const STATUS_CONTINUE: &str = "HTTP/1.1 100 Continue";
 
-const STATUS_NO_CONTENT: &'static str = "HTTP/1.1 204 No Content";
-const STATUS_UNAUTHORIZED: &'static str = "HTTP/1.1 401 Unauthorized";
-const SERVER_NAME: &'static str = "Gil HTTP";
+const STATUS_NO_CONTENT: &str = "HTTP/1.1 204 No Content";
+const STATUS_NOT_MODIFIED: &str = "HTTP/1.1 304 Not Modified";
+const STATUS_UNAUTHORIZED: &str = "HTTP/1.1 401 Unauthorized";
+const SERVER_NAME: &str = "Gil HTTP";
+
+pub const ACCEPT_RANGES: &str = "Accept-Ranges: bytes\r\n";
+pub const VARY_ACCEPT_ENCODING: &str = "Vary: Accept-Encoding\r\n";
+pub const TRANSFER_ENCODING_CHUNKED: &str = "Transfer-Encoding: chunked\r\n";
 
-const HEADER_AUTHENTICATE: &'static str =
-    "WWW-Authenticate: Basic realm=\"User Visible Realm\", charset=\"UTF-8\"";
 
 pub fn generate_option_headers(_: &str, _: usize, _: &str, _: &bool) -> LinkedHashSet<String> {
-    let allow = format!("Allow: OPTIONS, GET, HEAD\r\n");
+    let allow = "Allow: OPTIONS, GET, HEAD\r\n".to_string();
     let (status_line, cache_control, server) = generate_status_with_common_headers(STATUS_NO_CONTENT);
     let mut status_headers_set = LinkedHashSet::new();
     status_headers_set.insert(status_line);
     status_headers_set.insert(allow);
     status_headers_set.insert(cache_control);
     status_headers_set.insert(server);
-    return status_headers_set.clone();
+    status_headers_set.clone()
 }
 
-pub(crate) fn generate_authenticate_response(_: &str, _: usize, _: &str, _: &bool) -> LinkedHashSet<String> {
+/// A 401 response with one WWW-Authenticate header per challenge
+pub(crate) fn generate_authenticate_response(challenges: &[String]) -> LinkedHashSet<String> {
     let status_line = format!("{STATUS_UNAUTHORIZED}\r\n");
     let mut status_headers_set = LinkedHashSet::new();
     status_headers_set.insert(status_line);
-    status_headers_set.insert(HEADER_AUTHENTICATE.to_string());
-    return status_headers_set.clone();
+    for challenge in challenges {
+        status_headers_set.insert(format!("WWW-Authenticate: {challenge}\r\n"));
+    }
+    status_headers_set.insert("Content-Length: 0\r\n".to_string());
+    status_headers_set.clone()
 }
 
-pub fn generate_status_with_common_headers(status_line: &str) -> (String, String, String) {
-    let status_line = format!("{status_line}\r\n");
-    let cache_control = format!("Cache-Control: public, max-age=120\r\n");
-    let server = format!("Server: {SERVER_NAME}\r\n");
-    return (status_line, cache_control, server);
+/// A 429 response telling the client how many seconds to wait before trying again
+pub(crate) fn generate_too_many_requests_response(retry_after: u64) -> LinkedHashSet<String> {
+    let (status_line, _, server) = generate_status_with_common_headers(STATUS_TOO_MANY_REQUESTS);
+    let mut status_headers_set = LinkedHashSet::new();
+    status_headers_set.insert(status_line);
+    status_headers_set.insert(server);
+    status_headers_set.insert(format!("Retry-After: {retry_after}\r\n"));
+    status_headers_set.insert("Content-Length: 0\r\n".to_string());
+    status_headers_set
 }
 
+/// A redirect without content to the location
+pub(crate) fn generate_redirect_response(status_line: &str, location: &str) -> LinkedHashSet<String> {
+    let (status_line, _, server) = generate_status_with_common_headers(status_line);
+    let mut status_headers_set = LinkedHashSet::new();
+    status_headers_set.insert(status_line);
+    status_headers_set.insert(server);
+    status_headers_set.insert(format!("Location: {location}\r\n"));
+    status_headers_set.insert("Content-Length: 0\r\n".to_string());
+    status_headers_set
+}
 
+/// Tells browsers to only use HTTPS for the host for max_age seconds (RFC 6797). Only sent over TLS.
+pub(crate) fn generate_strict_transport_security(max_age: u64, include_subdomains: bool, preload: bool) -> String {
+    let include_subdomains = if include_subdomains { "; includeSubDomains" } else { "" };
+    let preload = if preload { "; preload" } else { "" };
+    format!("Strict-Transport-Security: max-age={max_age}{include_subdomains}{preload}\r\n")
+}
 
+/// A 304 response has no content, so it only carries the common headers and the validators
+pub(crate) fn generate_not_modified_headers() -> LinkedHashSet<String> {
+    let (status_line, cache_control, server) = generate_status_with_common_headers(STATUS_NOT_MODIFIED);
+    let mut status_headers_set = LinkedHashSet::new();
+    status_headers_set.insert(status_line);
+    status_headers_set.insert(cache_control);
+    status_headers_set.insert(server);
+    status_headers_set
+}
 
+pub fn generate_status_with_common_headers(status_line: &str) -> (String, String, String) {
+    let status_line = format!("{status_line}\r\n");
+    let cache_control = "Cache-Control: public, max-age=120\r\n".to_string();
+    let server = format!("Server: {SERVER_NAME}\r\n");
+    (status_line, cache_control, server)
+}
+
+pub(crate) fn generate_connection_headers(keep_alive: bool, timeout: u64, max: usize) -> LinkedHashSet<String> {
+    let mut connection_headers_set = LinkedHashSet::new();
+    if keep_alive {
+        connection_headers_set.insert("Connection: keep-alive\r\n".to_string());
+        connection_headers_set.insert(format!("Keep-Alive: timeout={timeout}, max={max}\r\n"));
+    } else {
+        connection_headers_set.insert("Connection: close\r\n".to_string());
+    }
+    connection_headers_set
+}
@@ -1,10 +1,12 @@
+use linked_hash_map::LinkedHashMap;
 use nom::bytes::complete::take_while;
 use nom::bytes::streaming::tag;
 use nom::character::is_space;
 use nom::IResult;
+use nom::multi::many0;
 use nom::sequence::tuple;
 
-use crate::http_parser::token;
+use crate::http_parser::{is_token_char, token};
 
 #[derive(PartialEq, Eq, Debug)]
 pub(crate) struct Header {
@@ -12,15 +14,76 @@ pub(crate) struct Header {
     pub(crate) value: Vec<u8>,
 }
 
-fn crlf(i: &[u8]) -> IResult<&[u8], &[u8]> {
+/// Case-insensitive, multi-valued header map which keeps the order in which the headers arrived.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub(crate) struct HeaderMap {
+    headers: LinkedHashMap<String, Vec<String>>,
+}
+
+impl HeaderMap {
+    pub(crate) fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, value: &str) {
+        self.headers.entry(name.to_ascii_lowercase())
+            .or_default()
+            .push(value.to_string());
+    }
+
+    /// Returns the first value of the header with the given name.
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase())
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Returns all values of the header with the given name in the order they were received.
+    pub(crate) fn get_all(&self, name: &str) -> Vec<&str> {
+        match self.headers.get(&name.to_ascii_lowercase()) {
+            Some(values) => values.iter().map(|value| value.as_str()).collect(),
+            None => vec![]
+        }
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks whether a comma separated header, like Connection, contains the token.
+    pub(crate) fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).iter()
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item=(&String, &Vec<String>)> {
+        self.headers.iter()
+    }
+}
+
+/// Parses a header added to every response, e.g. "X-Frame-Options: DENY"
+pub fn parse_response_header(header: &str) -> Result<String, String> {
+    let (name, value) = header.split_once(':')
+        .ok_or(format!("Expected a header like \"X-Frame-Options: DENY\", got {header}"))?;
+    let name = name.trim();
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(format!("Invalid header name {name}"));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("Invalid value of header {name}"));
+    }
+    Ok(format!("{name}: {}", value.trim()))
+}
+
+pub(crate) fn crlf(i: &[u8]) -> IResult<&[u8], &[u8]> {
     tag("\r\n")(i)
 }
 
 fn is_header_value_char(i: u8) -> bool {
-    i == 9 || (i >= 32 && i <= 126) || i >= 160
+    i == 9 || (32..=126).contains(&i) || i >= 160
 }
 
-// #[cfg(feature = "tolerant http1-parser")]
 pub(crate) fn message_header(i: &[u8]) -> IResult<&[u8], Header> {
     let (i, (name, _, _, value, _)) = tuple((
         token,
@@ -34,10 +97,30 @@ pub(crate) fn message_header(i: &[u8]) -> IResult<&[u8], Header> {
         Header { name: name.to_owned(), value: value.to_owned() }))
 }
 
+/// Parses all header lines up to (but excluding) the empty line into a [HeaderMap]
+pub(crate) fn message_headers(i: &[u8]) -> IResult<&[u8], HeaderMap> {
+    let (i, headers) = many0(message_header)(i)?;
+    let mut header_map = HeaderMap::new();
+    for header in headers {
+        let name = String::from_utf8_lossy(&header.name);
+        let value = String::from_utf8_lossy(&header.value);
+        header_map.insert(name.as_ref(), value.trim_end());
+    }
+    Ok((i, header_map))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn when_parse_response_header_should_normalize_spacing() {
+        assert_eq!(parse_response_header("X-Frame-Options:DENY ").unwrap(), "X-Frame-Options: DENY");
+        assert!(parse_response_header("X-Frame-Options").is_err());
+        assert!(parse_response_header("X Frame: DENY").is_err());
+        assert!(parse_response_header("X-Frame-Options: DENY\r\nSet-Cookie: a=b").is_err());
+    }
+
     #[test]
     fn when_message_header_should_produce_header() {
         let header = "Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n";
@@ -49,8 +132,34 @@ mod tests {
                 assert_eq!(header.1.value, "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".to_string().into_bytes())
             }
             Err(_) => {
-                assert!(false);
+                panic!("Header should have been parsed");
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn when_message_headers_should_be_case_insensitive_and_multi_valued() {
+        let headers = "Host: localhost\r\nAccept-Encoding: gzip\r\naccept-encoding: br \r\n\r\n";
+        let (remaining, header_map) = message_headers(headers.as_bytes()).unwrap();
+        assert_eq!(remaining, b"\r\n");
+        assert_eq!(header_map.get("HOST"), Some("localhost"));
+        assert_eq!(header_map.get_all("Accept-Encoding"), vec!["gzip", "br"]);
+        assert!(!header_map.contains("Range"));
+    }
+
+    #[test]
+    fn when_has_token_should_match_list_elements() {
+        let headers = "Connection: Upgrade, Keep-Alive\r\n\r\n";
+        let (_, header_map) = message_headers(headers.as_bytes()).unwrap();
+        assert!(header_map.has_token("connection", "keep-alive"));
+        assert!(!header_map.has_token("connection", "close"));
+    }
+
+    #[test]
+    fn when_message_headers_malformed_should_stop_before_line() {
+        let headers = "Host: localhost\r\nNot a header\r\n\r\n";
+        let (remaining, header_map) = message_headers(headers.as_bytes()).unwrap();
+        assert_eq!(remaining, b"Not a header\r\n\r\n");
+        assert_eq!(header_map.get("host"), Some("localhost"));
+    }
+}
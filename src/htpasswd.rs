@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use lazy_static::lazy_static;
+use md5::{Digest, Md5};
+use pwhash::{bcrypt, sha256_crypt, sha512_crypt};
+use subtle::ConstantTimeEq;
+
+use crate::bearer_auth::parse_tokens;
+
+lazy_static! {
+    /// Parsed htpasswd, htgroup, htdigest and token files by path and kind,
+    /// reloaded as soon as their size or modification time changes
+    static ref HTPASSWD_CACHE: Mutex<HashMap<(String, FileKind), HtpasswdFile>> = Mutex::new(HashMap::new());
+}
+
+const APR1_MAGIC: &str = "$apr1$";
+const APR1_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+/// Verified for unknown users when the file has no hash to take the cost from, the password is "dummy"
+const DUMMY_HASH: &str = "$2y$10$YqGkJAQi3wvIDlAoWDMiCOcnHWBOOU2nEeKZbJwLbdcsj5F3rl8hy";
+
+/// The formats of the files read through the cache. The same file can be read as more than one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum FileKind {
+    Htpasswd,
+    Htgroup,
+    Htdigest,
+    Tokens,
+}
+
+impl FileKind {
+    fn parse(&self, content: &str) -> Result<HashMap<String, String>, String> {
+        match self {
+            FileKind::Htpasswd => Ok(parse_htpasswd(content)),
+            FileKind::Htgroup => Ok(parse_htgroup(content)),
+            FileKind::Htdigest => Ok(parse_htdigest(content)),
+            FileKind::Tokens => parse_tokens(content),
+        }
+    }
+}
+
+struct HtpasswdFile {
+    length: u64,
+    modified: Option<SystemTime>,
+    entries: HashMap<String, String>,
+}
+
+/// Verifies the credentials against an Apache-style htpasswd file
+pub(crate) fn verify_htpasswd(path: &str, username: &str, password: &str) -> bool {
+    let (hash, dummy_hash) = match cached_file(path, FileKind::Htpasswd, |entries| {
+        // The hash of the first user, so that unknown users cost as much time as known ones
+        let dummy_hash = entries.iter().min_by_key(|(username, _)| *username).map(|(_, hash)| hash.clone());
+        (entries.get(username).cloned(), dummy_hash)
+    }) {
+        Some(hashes) => hashes,
+        None => return false
+    };
+    match hash {
+        Some(hash) => verify_password(password, &hash),
+        None => {
+            println!("Unknown user {username} in {path}");
+            verify_password(password, dummy_hash.as_deref().unwrap_or(DUMMY_HASH));
+            false
+        }
+    }
+}
+
+/// Whether the user has an entry in an Apache-style htpasswd file
+pub(crate) fn htpasswd_contains(path: &str, username: &str) -> bool {
+    cached_entry(path, username, FileKind::Htpasswd).is_some()
+}
+
+/// Checks the membership of the user in a group of an Apache-style htgroup file
+pub(crate) fn htgroup_contains(path: &str, group: &str, username: &str) -> bool {
+    cached_entry(path, group, FileKind::Htgroup)
+        .map(|members| members.split_whitespace().any(|member| member == username))
+        .unwrap_or(false)
+}
+
+/// Looks the key up in the file parsed as the given kind, parsing it again only when it changed
+pub(crate) fn cached_entry(path: &str, key: &str, kind: FileKind) -> Option<String> {
+    cached_file(path, kind, |entries| entries.get(key).cloned()).flatten()
+}
+
+fn cached_file<T>(path: &str, kind: FileKind, lookup: impl FnOnce(&HashMap<String, String>) -> T) -> Option<T> {
+    let metadata = fs::metadata(path)
+        .inspect_err(|e| println!("Cannot read {path}: {:?}", e.to_string()))
+        .ok()?;
+    let modified = metadata.modified().ok();
+    let cache_key = (path.to_string(), kind);
+    let mut cache = HTPASSWD_CACHE.lock().unwrap();
+    let changed = cache.get(&cache_key)
+        .map(|file| file.length != metadata.len() || file.modified != modified)
+        .unwrap_or(true);
+    if changed {
+        let content = fs::read_to_string(path)
+            .inspect_err(|e| println!("Cannot read {path}: {:?}", e.to_string()))
+            .ok()?;
+        let entries = match kind.parse(&content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("Cannot load {path}: {e}");
+                cache.remove(&cache_key);
+                return None;
+            }
+        };
+        println!("Loaded {} entries from {path}", entries.len());
+        cache.insert(cache_key.clone(), HtpasswdFile { length: metadata.len(), modified, entries });
+    }
+    cache.get(&cache_key).map(|file| lookup(&file.entries))
+}
+
+/// One "user:hash" entry per line, empty lines and comments starting with "#" are skipped
+fn parse_htpasswd(content: &str) -> HashMap<String, String> {
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(username, hash)| (username.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// The hex encoded H(username:realm:password) values of the user in an Apache-style htdigest file
+pub(crate) fn htdigest_ha1s(path: &str, username: &str, realm: &str) -> Vec<String> {
+    cached_entry(path, &format!("{username}:{realm}"), FileKind::Htdigest)
+        .map(|ha1s| ha1s.split_whitespace().map(|ha1| ha1.to_ascii_lowercase()).collect())
+        .unwrap_or_default()
+}
+
+/// One "user:realm:ha1" entry per line. A user can have an MD5 and a SHA-256 line for the same realm.
+fn parse_htdigest(content: &str) -> HashMap<String, String> {
+    let mut entries: HashMap<String, String> = HashMap::new();
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(':'))
+        .for_each(|(user_and_realm, ha1)| {
+            let ha1s = entries.entry(user_and_realm.to_string()).or_default();
+            ha1s.push(' ');
+            ha1s.push_str(ha1);
+        });
+    entries
+}
+
+/// One "group: user1 user2" entry per line
+fn parse_htgroup(content: &str) -> HashMap<String, String> {
+    parse_htpasswd(content).into_iter()
+        .map(|(group, members)| (group.trim().to_string(), members.trim().to_string()))
+        .collect()
+}
+
+/// Supports bcrypt ($2a$, $2b$, $2y$), SHA-crypt ($5$, $6$), argon2 ($argon2i$, $argon2d$, $argon2id$) and APR1-MD5.
+/// All of them compare the computed hash in constant time.
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        bcrypt::verify(password, hash)
+    } else if hash.starts_with("$5$") {
+        sha256_crypt::verify(password, hash)
+    } else if hash.starts_with("$6$") {
+        sha512_crypt::verify(password, hash)
+    } else if hash.starts_with("$argon2") {
+        PasswordHash::new(hash)
+            .and_then(|parsed_hash| Argon2::default().verify_password(password.as_bytes(), &parsed_hash))
+            .is_ok()
+    } else if let Some(salt_and_hash) = hash.strip_prefix(APR1_MAGIC) {
+        match salt_and_hash.split_once('$') {
+            Some((salt, _)) => apr1_crypt(password.as_bytes(), salt.as_bytes()).as_bytes().ct_eq(hash.as_bytes()).into(),
+            None => false
+        }
+    } else {
+        println!("Unsupported password hash format");
+        false
+    }
+}
+
+/// The MD5-based crypt of Apache, which only differs from $1$ MD5-crypt by its magic string
+fn apr1_crypt(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut context = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_MAGIC)
+        .chain_update(salt);
+    let mut remaining = password.len();
+    while remaining > 0 {
+        context.update(&alternate[..remaining.min(16)]);
+        remaining = remaining.saturating_sub(16);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 { context.update([0u8]) } else { context.update(&password[..1]) }
+        length >>= 1;
+    }
+    let mut digest = context.finalize();
+
+    for round in 0..1000 {
+        let mut round_context = Md5::new();
+        if round & 1 == 1 { round_context.update(password) } else { round_context.update(digest) }
+        if round % 3 != 0 {
+            round_context.update(salt);
+        }
+        if round % 7 != 0 {
+            round_context.update(password);
+        }
+        if round & 1 == 1 { round_context.update(digest) } else { round_context.update(password) }
+        digest = round_context.finalize();
+    }
+
+    let mut encoded = String::new();
+    let groups = [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)];
+    for (first, second, third) in groups {
+        let value = ((digest[first] as u32) << 16) | ((digest[second] as u32) << 8) | digest[third] as u32;
+        encode_apr1(&mut encoded, value, 4);
+    }
+    encode_apr1(&mut encoded, digest[11] as u32, 2);
+    format!("{APR1_MAGIC}{}${encoded}", String::from_utf8_lossy(salt))
+}
+
+fn encode_apr1(encoded: &mut String, mut value: u32, characters: usize) {
+    for _ in 0..characters {
+        encoded.push(APR1_ALPHABET[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    use super::*;
+
+    #[test]
+    fn when_parse_htpasswd_should_skip_comments() {
+        let entries = parse_htpasswd("# team\r\nalice:$apr1$x$y\r\n\r\nbob:$2y$05$abc\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries.get("alice").map(|hash| hash.as_str()), Some("$apr1$x$y"));
+    }
+
+    #[test]
+    fn when_parse_htdigest_should_collect_ha1s_per_realm() {
+        let entries = parse_htdigest("# digest\nalice:Reports:939e7578ed9e3c518a452acee763bce9\nalice:Reports:ABCD\nbob:Other:1234\n");
+        assert_eq!(entries.len(), 2);
+        let ha1s = entries.get("alice:Reports").unwrap().split_whitespace().collect::<Vec<_>>();
+        assert_eq!(ha1s, vec!["939e7578ed9e3c518a452acee763bce9", "ABCD"]);
+    }
+
+    #[test]
+    fn when_htgroup_contains_should_check_members() {
+        let path = std::env::temp_dir().join(format!("http_server_htgroup_{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "finance: alice bob\nadmins:carol\n").unwrap();
+        let memberships = (htgroup_contains(path_str, "finance", "bob"), htgroup_contains(path_str, "admins", "carol"),
+                           htgroup_contains(path_str, "admins", "alice"), htgroup_contains(path_str, "hr", "alice"));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(memberships, (true, true, false, false));
+    }
+
+    #[test]
+    fn when_verify_password_apr1_should_match_openssl() {
+        let hash = "$apr1$r31.....$G/cElGhD0cboYkZN5h5Ne/";
+        assert!(verify_password("secret", hash));
+        assert!(!verify_password("Secret", hash));
+    }
+
+    #[test]
+    fn when_verify_password_sha_crypt_should_match_openssl() {
+        assert!(verify_password("secret", "$5$saltsalt$0IyaXrmV7.sGNS6tirgqHLqX/G.FBvgkYA.lpPdS5sA"));
+        let sha512 = "$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1";
+        assert!(verify_password("secret", sha512));
+        assert!(!verify_password("secrets", sha512));
+    }
+
+    #[test]
+    fn when_verify_password_bcrypt_and_argon2_should_verify() {
+        let bcrypt_hash = bcrypt::hash_with(bcrypt::BcryptSetup {
+            cost: Some(4),
+            variant: Some(bcrypt::BcryptVariant::V2y),
+            ..Default::default()
+        }, "secret").unwrap();
+        assert!(bcrypt_hash.starts_with("$2y$"));
+        assert!(verify_password("secret", &bcrypt_hash));
+        assert!(!verify_password("wrong", &bcrypt_hash));
+
+        let salt = SaltString::encode_b64(b"saltsaltsalt").unwrap();
+        let argon2_hash = Argon2::default().hash_password(b"secret", &salt).unwrap().to_string();
+        assert!(verify_password("secret", &argon2_hash));
+        assert!(!verify_password("wrong", &argon2_hash));
+        assert!(!verify_password("secret", "secret"));
+    }
+
+    #[test]
+    fn when_htpasswd_file_changes_should_reload() {
+        let path = std::env::temp_dir().join(format!("http_server_htpasswd_{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "alice:$apr1$r31.....$G/cElGhD0cboYkZN5h5Ne/\n").unwrap();
+        let before = (verify_htpasswd(path_str, "alice", "secret"), verify_htpasswd(path_str, "bob", "secret"));
+        fs::write(&path, "bob:$apr1$r31.....$G/cElGhD0cboYkZN5h5Ne/\n# alice left\n").unwrap();
+        let after = (verify_htpasswd(path_str, "alice", "secret"), verify_htpasswd(path_str, "bob", "secret"));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(before, (true, false));
+        assert_eq!(after, (false, true));
+    }
+
+    #[test]
+    fn when_dummy_hash_is_verified_should_be_valid_bcrypt() {
+        assert!(verify_password("dummy", DUMMY_HASH));
+    }
+
+    #[test]
+    fn when_file_is_read_by_two_parsers_should_cache_both() {
+        let path = std::env::temp_dir().join(format!("http_server_htpasswd_htgroup_{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "alice:$apr1$r31.....$G/cElGhD0cboYkZN5h5Ne/
+admins: alice
+").unwrap();
+        let results = (htdigest_ha1s(path_str, "alice", "x").is_empty(), verify_htpasswd(path_str, "alice", "secret"),
+                       htgroup_contains(path_str, "admins", "alice"), verify_htpasswd(path_str, "bob", "secret"));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(results, (true, true, true, false));
+    }
+}
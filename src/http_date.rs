@@ -0,0 +1,50 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+const RFC_850_DATE: &str = "%A, %d-%b-%y %H:%M:%S GMT";
+const ASCTIME_DATE: &str = "%a %b %e %H:%M:%S %Y";
+
+/// Formats the time as IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub(crate) fn format_http_date(time: SystemTime) -> String {
+    let date_time: DateTime<Utc> = time.into();
+    date_time.format(IMF_FIXDATE).to_string()
+}
+
+/// Parses any of the three HTTP-date formats accepted by RFC 9110, section 5.6.7
+pub(crate) fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let naive_date_time = [IMF_FIXDATE, RFC_850_DATE, ASCTIME_DATE].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())?;
+    let seconds = u64::try_from(naive_date_time.and_utc().timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// HTTP dates have a resolution of one second, so file times are truncated before comparing them
+pub(crate) fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + Duration::from_secs(duration.as_secs()),
+        Err(_) => time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_format_http_date_should_produce_imf_fixdate() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn when_parse_http_date_should_accept_all_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+}
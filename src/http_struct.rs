@@ -1,11 +1,243 @@
-use std::net::TcpStream;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::time::Instant;
 
-use crate::MimeTypeProperties;
+use linked_hash_set::LinkedHashSet;
+use rustls::{ServerConnection, StreamOwned};
+
+use crate::args::ContentCoding;
+use crate::compression::{ChunkedWriter, compress};
+use crate::header_parser::HeaderMap;
+use crate::http_parser::Version;
+use crate::listener::Socket;
+use crate::{MimeTypeProperties, RunCommand};
 
 pub(crate) struct HttpData<'a> {
-    pub(crate) stream: &'a mut TcpStream,
+    pub(crate) stream: &'a mut ClientStream,
     pub(crate) uri: String,
     pub(crate) mime_type_map: &'a MimeTypeProperties,
     pub(crate) is_head : &'a bool,
-    pub(crate) root_folder: &'a String
-}
\ No newline at end of file
+    pub(crate) root_folder: &'a String,
+    pub(crate) request_headers: &'a HeaderMap,
+    pub(crate) version: Version,
+    pub(crate) run_args: &'a RunCommand,
+}
+
+/// The socket of a client, either plain or encrypted with TLS
+pub(crate) enum Connection {
+    Plain(Socket),
+    Tls(Box<StreamOwned<ServerConnection, Socket>>),
+}
+
+impl Connection {
+    fn socket(&self) -> &Socket {
+        match self {
+            Connection::Plain(stream) => stream,
+            Connection::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+pub(crate) struct DeadlineReader<'a> {
+    reader: &'a mut BufReader<Connection>,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl BufRead for DeadlineReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.reader.buffer().is_empty() {
+            let remaining = self.deadline.checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or(io::Error::new(ErrorKind::TimedOut, "Deadline passed"))?;
+            self.reader.get_ref().socket().set_read_timeout(Some(remaining))?;
+        }
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+/// Client connection which is read through a buffer, so that pipelined requests are not lost
+/// between two reads, together with the headers added to every response written to it.
+pub(crate) struct ClientStream {
+    reader: BufReader<Connection>,
+    pub(crate) response_headers: LinkedHashSet<String>,
+}
+
+impl ClientStream {
+    pub(crate) fn new(connection: Connection) -> ClientStream {
+        ClientStream {
+            reader: BufReader::new(connection),
+            response_headers: LinkedHashSet::new(),
+        }
+    }
+
+    pub(crate) fn reader(&mut self) -> &mut BufReader<Connection> {
+        &mut self.reader
+    }
+
+    pub(crate) fn socket(&self) -> &Socket {
+        self.reader.get_ref().socket()
+    }
+
+    /// Reads through the buffer like reader(), but all reads from the socket together end at the deadline,
+    /// so a client sending a byte now and then can't hold the connection
+    pub(crate) fn reader_until(&mut self, deadline: Instant) -> DeadlineReader<'_> {
+        DeadlineReader { reader: &mut self.reader, deadline }
+    }
+
+    /// The TLS session of the connection, None for plain HTTP
+    pub(crate) fn tls_connection(&self) -> Option<&ServerConnection> {
+        match self.reader.get_ref() {
+            Connection::Plain(_) => None,
+            Connection::Tls(stream) => Some(&stream.conn),
+        }
+    }
+
+    /// Runs the TLS handshake, so that a failed one is not mistaken for a malformed request
+    pub(crate) fn complete_handshake(&mut self) -> io::Result<()> {
+        if let Connection::Tls(stream) = self.reader.get_mut() {
+            while stream.conn.is_handshaking() {
+                stream.conn.complete_io(&mut stream.sock)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies length bytes from the current position of the file to the connection in bounded chunks.
+    /// Writing directly to a plain TcpStream or UnixStream lets std use sendfile or copy_file_range on Linux.
+    pub(crate) fn copy_file(&mut self, file: &mut File, length: u64) -> io::Result<u64> {
+        let result = match self.reader.get_mut() {
+            Connection::Plain(Socket::Tcp(stream)) => io::copy(&mut file.take(length), stream),
+            #[cfg(unix)]
+            Connection::Plain(Socket::Unix(stream)) => io::copy(&mut file.take(length), stream),
+            Connection::Tls(stream) => io::copy(&mut file.take(length), stream),
+        };
+        let copied = result.inspect_err(|_| self.abort())?;
+        if copied < length {
+            self.abort();
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "File shorter than announced Content-Length"));
+        }
+        Ok(copied)
+    }
+
+    /// Compresses length bytes from the current position of the file to the connection,
+    /// framed with the chunked transfer coding as the compressed length is not known in advance.
+    pub(crate) fn copy_file_compressed(&mut self, file: &mut File, length: u64, coding: ContentCoding) -> io::Result<u64> {
+        let writer = ChunkedWriter::buffered(self.reader.get_mut());
+        let result = compress(&mut file.take(length), coding, writer)
+            .and_then(|(copied, writer)| {
+                writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+                Ok(copied)
+            });
+        match result {
+            Ok(copied) if copied == length => Ok(copied),
+            Ok(_) => {
+                self.abort();
+                Err(io::Error::new(ErrorKind::UnexpectedEof, "File shorter than its metadata length"))
+            }
+            Err(e) => {
+                self.abort();
+                Err(e)
+            }
+        }
+    }
+
+    /// Closes the connection after a failed write, as the response framing can no longer be trusted
+    fn abort(&mut self) {
+        let _ = self.socket().shutdown(Shutdown::Both);
+    }
+}
+
+impl Drop for ClientStream {
+    /// Announces the end of a TLS session, so that the client can tell it from a truncation
+    fn drop(&mut self) {
+        if let Connection::Tls(stream) = self.reader.get_mut() {
+            stream.conn.send_close_notify();
+            let _ = stream.flush();
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+            .inspect_err(|_| self.abort())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::request_reader::read_request_head;
+
+    use super::*;
+
+    #[test]
+    fn when_client_trickles_request_head_should_time_out_at_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut stream = ClientStream::new(Connection::Plain(Socket::Tcp(server)));
+        let trickle = thread::spawn(move || {
+            for byte in b"GET / HTTP/1.1\r\n" {
+                if client.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let started = Instant::now();
+        let error = read_request_head(&mut stream.reader_until(started + Duration::from_millis(300))).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(stream);
+        trickle.join().unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use crate::args::RunCommand;
+use crate::http_parser::{Uri, UriForm};
+use crate::listener::ListenAddress;
+use crate::string_operations::percent_encode;
+
+const HTTPS_PORT: u16 = 443;
+
+/// Removes the port from a host like "example.com:8080" or "[2001:db8::1]:8080",
+/// None when what is left is neither a host name nor an address
+pub(crate) fn host_without_port(host: &str) -> Option<&str> {
+    let host = match host.strip_prefix('[') {
+        Some(address) => {
+            let end = address.find(']')?;
+            let address = &address[..end];
+            if address.is_empty() || !address.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.') {
+                return None;
+            }
+            &host[..end + 2]
+        }
+        None => {
+            let name = host.split(':').next().unwrap_or("");
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+                return None;
+            }
+            name
+        }
+    };
+    Some(host)
+}
+
+/// The port redirects point to: the TLS port, or else the port of the first TLS listener
+pub(crate) fn https_port(run_args: &RunCommand) -> Option<u16> {
+    run_args.tls_port.or_else(|| run_args.listen.iter()
+        .filter(|listen| listen.tls)
+        .find_map(|listen| match &listen.address {
+            ListenAddress::Tcp(address) => Some(address.port()),
+            ListenAddress::Host(_, port) => Some(*port),
+            ListenAddress::Unix(_) => None,
+        }))
+}
+
+/// The https URL of the request target on the host the client asked for and the TLS port
+pub(crate) fn redirect_location(uri: &Uri, host: &str, tls_port: u16) -> Option<String> {
+    let host = host_without_port(host)?;
+    let port = if tls_port == HTTPS_PORT { String::new() } else { format!(":{tls_port}") };
+    let path = match uri.form {
+        UriForm::Asterisk => "/".to_string(),
+        _ => percent_encode(&uri.path)
+    };
+    let query = uri.query.as_ref().map(|query| format!("?{query}")).unwrap_or_default();
+    Some(format!("https://{host}{port}{path}{query}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(target: &str, host: &str, tls_port: u16) -> Option<String> {
+        redirect_location(&Uri::parse(target).unwrap(), host, tls_port)
+    }
+
+    #[test]
+    fn when_redirect_location_should_keep_path_and_query() {
+        assert_eq!(location("/docs/a%20b.html?x=1&y", "example.com:8080", 443).unwrap(), "https://example.com/docs/a%20b.html?x=1&y");
+        assert_eq!(location("/", "example.com", 8443).unwrap(), "https://example.com:8443/");
+        assert_eq!(location("http://other.example/a", "other.example", 443).unwrap(), "https://other.example/a");
+        assert_eq!(location("*", "[2001:db8::1]:80", 443).unwrap(), "https://[2001:db8::1]/");
+    }
+
+    #[test]
+    fn when_host_is_invalid_should_not_redirect() {
+        assert!(location("/", "", 443).is_none());
+        assert!(location("/", "evil.example/path", 443).is_none());
+        assert!(location("/", "user@evil.example", 443).is_none());
+        assert!(location("/", "[2001:db8::1", 443).is_none());
+    }
+}
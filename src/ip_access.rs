@@ -0,0 +1,123 @@
+use std::net::IpAddr;
+
+use crate::ip_network::{IpNetwork, parse_ip_network};
+use crate::string_operations::matches_path_prefix;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAction {
+    Allow,
+    Deny,
+}
+
+/// How the address check combines with the credentials of the protected folders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Satisfy {
+    /// The address has to be allowed and the credentials have to be valid
+    All,
+    /// An allowed address or valid credentials are enough
+    Any,
+}
+
+/// Ordered allow and deny entries for a path prefix. The first entry matching the client address decides,
+/// addresses matching no entry get the default.
+#[derive(Debug, Clone)]
+pub struct IpRule {
+    pub path_prefix: String,
+    pub entries: Vec<(IpAction, Vec<IpNetwork>)>,
+    pub default: IpAction,
+    pub satisfy: Satisfy,
+}
+
+impl IpRule {
+    pub(crate) fn allows(&self, client_ip: Option<IpAddr>) -> bool {
+        let action = client_ip
+            .and_then(|ip| self.entries.iter().find(|(_, networks)| networks.iter().any(|network| network.contains(ip))))
+            .map(|(action, _)| *action)
+            .unwrap_or(self.default);
+        action == IpAction::Allow
+    }
+}
+
+/// Parses a rule like "/intranet;deny=10.9.0.0/16;allow=10.0.0.0/8,2001:db8::/32;default=deny;satisfy=any".
+/// The default is deny and satisfy is all, so the address has to be allowed on top of the credentials.
+pub fn parse_ip_rule(rule: &str) -> Result<IpRule, String> {
+    let mut parts = rule.split(';').map(|part| part.trim());
+    let path_prefix = parts.next().unwrap_or("").to_string();
+    if !path_prefix.starts_with('/') {
+        return Err(format!("Path prefix {path_prefix} has to start with /"));
+    }
+    let mut ip_rule = IpRule { path_prefix, entries: vec![], default: IpAction::Deny, satisfy: Satisfy::All };
+    for part in parts.filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        let action = |value: &str| match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(IpAction::Allow),
+            "deny" => Ok(IpAction::Deny),
+            _ => Err(format!("Unknown action in {part}"))
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "allow" | "deny" => {
+                let networks = value.split(',')
+                    .filter(|network| !network.trim().is_empty())
+                    .map(parse_ip_network)
+                    .collect::<Result<Vec<_>, _>>()?;
+                ip_rule.entries.push((action(key)?, networks));
+            }
+            "default" => ip_rule.default = action(value)?,
+            "satisfy" => ip_rule.satisfy = match value.trim().to_ascii_lowercase().as_str() {
+                "all" => Satisfy::All,
+                "any" => Satisfy::Any,
+                _ => return Err(format!("Unknown satisfy value in {part}, use all or any"))
+            },
+            _ => return Err(format!("Unknown IP rule setting {part}"))
+        }
+    }
+    Ok(ip_rule)
+}
+
+/// The rule with the longest prefix matching the path
+pub(crate) fn find_ip_rule<'a>(path: &str, rules: &'a [IpRule]) -> Option<&'a IpRule> {
+    rules.iter()
+        .filter(|rule| matches_path_prefix(path, &rule.path_prefix))
+        .max_by_key(|rule| rule.path_prefix.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        address.parse().ok()
+    }
+
+    #[test]
+    fn when_parse_ip_rule_should_keep_entry_order() {
+        let rule = parse_ip_rule("/intranet;deny=10.9.0.0/16;allow=10.0.0.0/8,2001:db8::/32;satisfy=any").unwrap();
+        assert_eq!(rule.entries.len(), 2);
+        assert_eq!(rule.entries[0].0, IpAction::Deny);
+        assert_eq!(rule.default, IpAction::Deny);
+        assert_eq!(rule.satisfy, Satisfy::Any);
+        assert!(parse_ip_rule("intranet;allow=10.0.0.0/8").is_err());
+        assert!(parse_ip_rule("/intranet;allow=10.0.0.0/40").is_err());
+        assert!(parse_ip_rule("/intranet;satisfy=some").is_err());
+    }
+
+    #[test]
+    fn when_allows_should_use_first_matching_entry() {
+        let rule = parse_ip_rule("/intranet;deny=10.9.0.0/16;allow=10.0.0.0/8,2001:db8::/32").unwrap();
+        assert!(rule.allows(ip("10.1.2.3")));
+        assert!(!rule.allows(ip("10.9.2.3")));
+        assert!(rule.allows(ip("2001:db8::5")));
+        assert!(!rule.allows(ip("203.0.113.1")));
+        assert!(!rule.allows(None));
+        let open = parse_ip_rule("/;deny=198.51.100.0/24;default=allow").unwrap();
+        assert!(open.allows(ip("203.0.113.1")));
+        assert!(!open.allows(ip("198.51.100.7")));
+    }
+
+    #[test]
+    fn when_find_ip_rule_should_prefer_longest_prefix() {
+        let rules = vec![parse_ip_rule("/;default=allow").unwrap(), parse_ip_rule("/intranet;allow=10.0.0.0/8").unwrap()];
+        assert_eq!(find_ip_rule("/intranet/a.txt", &rules).unwrap().path_prefix, "/intranet");
+        assert_eq!(find_ip_rule("/intranetx", &rules).unwrap().path_prefix, "/");
+    }
+}
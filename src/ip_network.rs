@@ -0,0 +1,86 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/// An IPv4 or IPv6 network in CIDR notation. A plain address is a network with a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) =>
+                masked(u32::from(network) as u128, 32, self.prefix_length) == masked(u32::from(ip) as u128, 32, self.prefix_length),
+            (IpAddr::V6(network), IpAddr::V6(ip)) =>
+                masked(u128::from(network), 128, self.prefix_length) == masked(u128::from(ip), 128, self.prefix_length),
+            _ => false
+        }
+    }
+}
+
+fn masked(address: u128, bits: u8, prefix_length: u8) -> u128 {
+    let host_bits = (bits - prefix_length) as u32;
+    address.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// Parses "10.0.0.0/8", "2001:db8::/32" or a single address like "192.168.1.7"
+pub fn parse_ip_network(network: &str) -> Result<IpNetwork, String> {
+    let (address, prefix_length) = network.trim().split_once('/').unwrap_or((network.trim(), ""));
+    let address = address.parse::<IpAddr>()
+        .map_err(|_| format!("Invalid network address {address}"))?
+        .to_canonical();
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_length = match prefix_length {
+        "" => bits,
+        length => length.parse::<u8>().ok()
+            .filter(|length| *length <= bits)
+            .ok_or(format!("Invalid prefix length in {network}"))?
+    };
+    Ok(IpNetwork { address, prefix_length })
+}
+
+/// Checks whether the address lies in any of the networks
+pub(crate) fn in_networks(networks: &[IpNetwork], ip: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn when_parse_ip_network_should_accept_cidr_and_addresses() {
+        assert_eq!(parse_ip_network("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse_ip_network("192.168.1.7").unwrap().to_string(), "192.168.1.7/32");
+        assert_eq!(parse_ip_network("2001:db8::/32").unwrap().to_string(), "2001:db8::/32");
+        assert_eq!(parse_ip_network("::ffff:10.1.2.3").unwrap().to_string(), "10.1.2.3/32");
+        assert!(parse_ip_network("10.0.0.0/33").is_err());
+        assert!(parse_ip_network("example.com").is_err());
+    }
+
+    #[test]
+    fn when_contains_should_compare_prefix_bits() {
+        let private = parse_ip_network("10.0.0.0/8").unwrap();
+        assert!(private.contains(ip("10.200.3.4")));
+        assert!(private.contains(ip("::ffff:10.200.3.4")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(!private.contains(ip("::1")));
+        let documentation = parse_ip_network("2001:db8::/32").unwrap();
+        assert!(documentation.contains(ip("2001:db8:1::7")));
+        assert!(!documentation.contains(ip("2001:db9::7")));
+        assert!(parse_ip_network("0.0.0.0/0").unwrap().contains(ip("203.0.113.9")));
+        assert!(parse_ip_network("::/0").unwrap().contains(ip("2001:db8::1")));
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use socket2::{Domain, Socket as RawSocket, Type};
+
+const UNIX_PREFIX: &str = "unix:";
+const BACKLOG: i32 = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    /// An IP address with its port, "[::]" accepts IPv4 clients as well unless the listener is IPv6 only
+    Tcp(SocketAddr),
+    /// A host name which is resolved when binding
+    Host(String, u16),
+    /// The path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{address}"),
+            ListenAddress::Host(host, port) => write!(f, "{host}:{port}"),
+            ListenAddress::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+/// An address the server accepts connections on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listen {
+    pub address: ListenAddress,
+    pub tls: bool,
+    pub ipv6_only: bool,
+    /// Permission bits of a Unix domain socket, e.g. 0o660
+    pub mode: Option<u32>,
+}
+
+impl Listen {
+    pub(crate) fn host(host: &str, port: u16, tls: bool) -> Listen {
+        let address = match host.parse::<IpAddr>() {
+            Ok(ip) => ListenAddress::Tcp(SocketAddr::new(ip, port)),
+            Err(_) => ListenAddress::Host(host.to_string(), port),
+        };
+        Listen { address, tls, ipv6_only: false, mode: None }
+    }
+}
+
+/// Parses a listener like "[::]:443;tls", "0.0.0.0:80", "localhost:8080" or "unix:/run/http_server.sock;mode=660"
+pub fn parse_listen(listen: &str) -> Result<Listen, String> {
+    let mut parts = listen.split(';').map(|part| part.trim());
+    let address = parts.next().unwrap_or("");
+    let address = if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+        if !cfg!(unix) {
+            return Err("Unix domain sockets are not supported on this platform".to_string());
+        }
+        if path.is_empty() {
+            return Err(format!("Missing socket path in {listen}"));
+        }
+        ListenAddress::Unix(PathBuf::from(path))
+    } else if let Ok(address) = address.parse::<SocketAddr>() {
+        ListenAddress::Tcp(address)
+    } else {
+        let (host, port) = address.rsplit_once(':').ok_or(format!("Missing port in {address}"))?;
+        let port = port.parse::<u16>().map_err(|_| format!("Invalid port in {address}"))?;
+        if host.is_empty() || !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            return Err(format!("Invalid host in {address}"));
+        }
+        ListenAddress::Host(host.to_string(), port)
+    };
+    let mut listen = Listen { address, tls: false, ipv6_only: false, mode: None };
+    for part in parts.filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        match key.trim().to_ascii_lowercase().as_str() {
+            "tls" => listen.tls = true,
+            "ipv6-only" if matches!(listen.address, ListenAddress::Tcp(SocketAddr::V6(_))) => listen.ipv6_only = true,
+            "ipv6-only" => return Err(format!("{part} needs an IPv6 address")),
+            "mode" if matches!(listen.address, ListenAddress::Unix(_)) => {
+                let value = value.trim();
+                let mode = u32::from_str_radix(value.strip_prefix("0o").unwrap_or(value), 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or(format!("Invalid permissions in {part}, use octal digits like 660"))?;
+                listen.mode = Some(mode);
+            }
+            "mode" => return Err(format!("{part} needs a Unix domain socket")),
+            _ => return Err(format!("Unknown listen setting {part}"))
+        }
+    }
+    Ok(listen)
+}
+
+/// A bound socket accepting connections
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub(crate) fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Socket::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Socket::Unix(stream)),
+        }
+    }
+}
+
+pub(crate) fn bind(listen: &Listen) -> io::Result<Listener> {
+    match &listen.address {
+        ListenAddress::Tcp(address) => {
+            let socket = RawSocket::new(Domain::for_address(*address), Type::STREAM, None)?;
+            // Dual-stack is set explicitly, as the system default differs between platforms
+            if address.is_ipv6() {
+                socket.set_only_v6(listen.ipv6_only)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.bind(&(*address).into())?;
+            socket.listen(BACKLOG)?;
+            Ok(Listener::Tcp(socket.into()))
+        }
+        ListenAddress::Host(host, port) => TcpListener::bind((host.as_str(), *port)).map(Listener::Tcp),
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => {
+            // A socket file left behind by a server which is gone would make the bind fail
+            if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(ErrorKind::AddrInUse, "Another server listens on the socket"));
+                }
+                fs::remove_file(path)?;
+            }
+            let listener = match listen.mode {
+                Some(mode) => bind_unix_with_mode(path, mode)?,
+                None => UnixListener::bind(path)?,
+            };
+            Ok(Listener::Unix(listener))
+        }
+        #[cfg(not(unix))]
+        ListenAddress::Unix(_) => Err(io::Error::new(ErrorKind::Unsupported, "Unix domain sockets are not supported")),
+    }
+}
+
+/// Binds the socket in a private folder next to the path and moves it in place once it has its permissions,
+/// so that it can't be connected to with the permissions of the umask in between
+#[cfg(unix)]
+fn bind_unix_with_mode(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let private_folder = parent.join(format!(".http_server_{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private_folder)?;
+    let staged_path = private_folder.join("socket");
+    let listener = UnixListener::bind(&staged_path)
+        .and_then(|listener| fs::set_permissions(&staged_path, fs::Permissions::from_mode(mode)).map(|_| listener))
+        .and_then(|listener| fs::rename(&staged_path, path).map(|_| listener));
+    let _ = fs::remove_file(&staged_path);
+    fs::remove_dir(&private_folder)?;
+    listener
+}
+
+/// The socket of a client connection, over TCP or a Unix domain socket
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Whether the client connected over TCP rather than a Unix domain socket
+    pub(crate) fn is_tcp(&self) -> bool {
+        matches!(self, Socket::Tcp(_))
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// The address of the client, None on a Unix domain socket
+    pub(crate) fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().ok().map(|address| address.ip()),
+            #[cfg(unix)]
+            Socket::Unix(_) => None,
+        }
+    }
+
+    /// The address the client connected to, None on a Unix domain socket
+    pub(crate) fn local_ip(&self) -> Option<IpAddr> {
+        match self {
+            Socket::Tcp(stream) => stream.local_addr().ok().map(|address| address.ip()),
+            #[cfg(unix)]
+            Socket::Unix(_) => None,
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn when_parse_listen_should_read_address_and_settings() {
+        let listen = parse_listen("[::]:443;tls").unwrap();
+        assert_eq!(listen.address, ListenAddress::Tcp(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 443)));
+        assert!(listen.tls && !listen.ipv6_only);
+        let listen = parse_listen("0.0.0.0:80").unwrap();
+        assert_eq!(listen.address, ListenAddress::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 80)));
+        assert!(parse_listen("[::1]:80;ipv6-only").unwrap().ipv6_only);
+        assert_eq!(parse_listen("localhost:8080").unwrap().address, ListenAddress::Host("localhost".to_string(), 8080));
+        let listen = parse_listen("unix:/run/http_server.sock;mode=0660").unwrap();
+        assert_eq!(listen.address, ListenAddress::Unix(PathBuf::from("/run/http_server.sock")));
+        assert_eq!(listen.mode, Some(0o660));
+    }
+
+    #[test]
+    fn when_listen_is_invalid_should_fail() {
+        assert!(parse_listen("0.0.0.0").is_err());
+        assert!(parse_listen("0.0.0.0:http").is_err());
+        assert!(parse_listen("0.0.0.0:80;ipv6-only").is_err());
+        assert!(parse_listen("0.0.0.0:80;mode=660").is_err());
+        assert!(parse_listen("unix:/run/http_server.sock;mode=999").is_err());
+        assert!(parse_listen("unix:").is_err());
+        assert!(parse_listen("[::]:80;secure").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn when_bind_unix_socket_should_replace_stale_socket_and_set_mode() {
+        let path = std::env::temp_dir().join(format!("http_server_listen_{}.sock", std::process::id()));
+        drop(UnixListener::bind(&path).unwrap());
+        let listen = Listen { address: ListenAddress::Unix(path.clone()), tls: false, ipv6_only: false, mode: Some(0o600) };
+        let listener = bind(&listen).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!path.with_file_name(format!(".http_server_{}", std::process::id())).exists());
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        let mut socket = listener.accept().unwrap();
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(socket.peer_ip(), None);
+        assert!(!socket.is_tcp());
+        assert_eq!(bind(&listen).err().map(|e| e.kind()), Some(ErrorKind::AddrInUse));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+
+use crate::ip_network::in_networks;
+use crate::RunCommand;
+
+lazy_static! {
+    /// Failed authentication attempts by client address and by user name
+    static ref FAILURES: Mutex<FailureTable> = Mutex::new(FailureTable::default());
+}
+
+/// Above this number of records the oldest ones are forgotten, so failing clients can't exhaust memory
+const MAX_FAILURE_RECORDS: usize = 10_000;
+
+struct FailureRecord {
+    failures: u32,
+    lockouts: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl FailureRecord {
+    fn is_expired(&self, now: Instant, max_lockout: Duration) -> bool {
+        now.duration_since(self.last_failure) >= max_lockout
+            && self.locked_until.is_none_or(|locked_until| locked_until <= now)
+    }
+}
+
+/// The records with their keys in the order they were created, records are only ever removed from the front
+#[derive(Default)]
+struct FailureTable {
+    records: HashMap<String, FailureRecord>,
+    order: VecDeque<String>,
+}
+
+impl FailureTable {
+    fn record(&mut self, key: &str, now: Instant, max_lockout: Duration, max_records: usize) -> &mut FailureRecord {
+        // Records of clients which stopped failing are forgotten, checking only the oldest ones keeps this cheap
+        while let Some(oldest) = self.order.front() {
+            let expired = self.records.get(oldest).is_none_or(|record| record.is_expired(now, max_lockout));
+            if !expired && (self.records.contains_key(key) || self.records.len() < max_records) {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.records.remove(&oldest);
+            }
+        }
+        if !self.records.contains_key(key) {
+            self.order.push_back(key.to_string());
+        }
+        self.records.entry(key.to_string()).or_insert(FailureRecord {
+            failures: 0,
+            lockouts: 0,
+            last_failure: now,
+            locked_until: None,
+        })
+    }
+}
+
+fn keys(client_ip: Option<IpAddr>, username: Option<&str>) -> Vec<String> {
+    let ip_key = client_ip.map(|ip| format!("ip:{}", ip.to_canonical()));
+    let user_key = username.map(|username| format!("user:{username}"));
+    ip_key.into_iter().chain(user_key).collect()
+}
+
+fn is_exempt(run_args: &RunCommand, client_ip: Option<IpAddr>) -> bool {
+    run_args.max_auth_failures == 0 || client_ip.is_some_and(|ip| in_networks(&run_args.trusted_networks, ip))
+}
+
+/// The time left until the client address or the user may try again, None when neither is locked out
+pub(crate) fn locked_out(run_args: &RunCommand, client_ip: Option<IpAddr>, username: Option<&str>) -> Option<Duration> {
+    if is_exempt(run_args, client_ip) {
+        return None;
+    }
+    let now = Instant::now();
+    let failures = FAILURES.lock().unwrap();
+    keys(client_ip, username).iter()
+        .filter_map(|key| failures.records.get(key)?.locked_until)
+        .filter(|locked_until| *locked_until > now)
+        .map(|locked_until| locked_until - now)
+        .max()
+}
+
+/// Counts the failure against the client address and the user, which has to be a known one. Reaching the maximum number of failures locks them out,
+/// each lockout twice as long as the one before, up to the maximum lockout.
+pub(crate) fn record_failure(run_args: &RunCommand, client_ip: Option<IpAddr>, username: Option<&str>, path: &str) {
+    audit(run_args, "AUTH_FAILURE", client_ip, username, path);
+    if is_exempt(run_args, client_ip) {
+        return;
+    }
+    let now = Instant::now();
+    let max_lockout = Duration::from_secs(run_args.max_lockout_seconds);
+    let mut failures = FAILURES.lock().unwrap();
+    let mut lockouts = vec![];
+    for key in keys(client_ip, username) {
+        let record = failures.record(&key, now, max_lockout, MAX_FAILURE_RECORDS);
+        record.failures += 1;
+        record.last_failure = now;
+        if record.failures >= run_args.max_auth_failures {
+            let lockout = Duration::from_secs(run_args.lockout_seconds)
+                .saturating_mul(2u32.saturating_pow(record.lockouts))
+                .min(max_lockout);
+            record.failures = 0;
+            record.lockouts += 1;
+            record.locked_until = Some(now + lockout);
+            lockouts.push(format!("LOCKOUT {key} seconds={}", lockout.as_secs()));
+        }
+    }
+    drop(failures);
+    for lockout in lockouts {
+        audit(run_args, &lockout, client_ip, username, path);
+    }
+}
+
+/// A successful login clears the failures of the user, the failures of the address only fade out
+pub(crate) fn record_success(username: Option<&str>) {
+    if let Some(key) = keys(None, username).first() {
+        if let Some(record) = FAILURES.lock().unwrap().records.get_mut(key) {
+            record.failures = 0;
+            record.lockouts = 0;
+            record.locked_until = None;
+        }
+    }
+}
+
+/// Writes the event to the standard output and, when configured, appends it to the audit log
+fn audit(run_args: &RunCommand, event: &str, client_ip: Option<IpAddr>, username: Option<&str>, path: &str) {
+    let client_ip = client_ip.map(|ip| ip.to_string()).unwrap_or("-".to_string());
+    let entry = format!("{} {event} ip={client_ip} user={} path={path}",
+                        Utc::now().to_rfc3339(), username.unwrap_or("-"));
+    println!("{entry}");
+    if let Some(audit_log) = &run_args.audit_log {
+        let written = OpenOptions::new().create(true).append(true).open(audit_log)
+            .and_then(|mut file| file.write_all(format!("{entry}\n").as_bytes()));
+        if let Err(e) = written {
+            println!("Cannot write to {audit_log}: {:?}", e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::args::{HttpServerArgs, Mode};
+
+    use super::*;
+
+    fn run_command_factory(options: &[&str]) -> RunCommand {
+        let args = ["http_server", "run", "--port", "80", "--host", "127.0.0.1"].iter()
+            .chain(options)
+            .chain(&["none"]);
+        match HttpServerArgs::parse_from(args).mode {
+            Mode::Run(run_command) => *run_command,
+            _ => unreachable!()
+        }
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        address.parse().ok()
+    }
+
+    #[test]
+    fn when_record_failure_should_lock_out_with_backoff() {
+        let run_args = run_command_factory(&["--max-auth-failures", "2", "--lockout-seconds", "10", "--max-lockout-seconds", "30"]);
+        let client = ip("198.51.100.1");
+        record_failure(&run_args, client, None, "/data");
+        assert!(locked_out(&run_args, client, None).is_none());
+        record_failure(&run_args, client, None, "/data");
+        let first = locked_out(&run_args, client, None).unwrap();
+        assert!(first > Duration::from_secs(9) && first <= Duration::from_secs(10));
+
+        record_failure(&run_args, client, None, "/data");
+        record_failure(&run_args, client, None, "/data");
+        assert!(locked_out(&run_args, client, None).unwrap() > Duration::from_secs(19));
+        record_failure(&run_args, client, None, "/data");
+        record_failure(&run_args, client, None, "/data");
+        assert!(locked_out(&run_args, client, None).unwrap() <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn when_record_failure_should_lock_out_user_from_every_address() {
+        let run_args = run_command_factory(&["--max-auth-failures", "2"]);
+        record_failure(&run_args, ip("198.51.100.2"), Some("mallory-target"), "/data");
+        record_failure(&run_args, ip("198.51.100.3"), Some("mallory-target"), "/data");
+        assert!(locked_out(&run_args, ip("198.51.100.4"), Some("mallory-target")).is_some());
+        assert!(locked_out(&run_args, ip("198.51.100.4"), Some("someone-else")).is_none());
+        record_success(Some("mallory-target"));
+        assert!(locked_out(&run_args, ip("198.51.100.4"), Some("mallory-target")).is_none());
+    }
+
+    #[test]
+    fn when_client_is_trusted_should_never_lock_out() {
+        let run_args = run_command_factory(&["--max-auth-failures", "1", "--trusted-networks", "203.0.113.0/24,2001:db8::/32"]);
+        record_failure(&run_args, ip("203.0.113.5"), None, "/data");
+        assert!(locked_out(&run_args, ip("203.0.113.5"), None).is_none());
+        record_failure(&run_args, ip("203.0.114.5"), None, "/data");
+        assert!(locked_out(&run_args, ip("203.0.114.5"), None).is_some());
+    }
+
+    #[test]
+    fn when_failure_table_is_full_should_forget_oldest_records() {
+        let mut table = FailureTable::default();
+        let now = Instant::now();
+        let max_lockout = Duration::from_secs(3600);
+        for key in ["user:a", "user:b", "user:c"] {
+            table.record(key, now, max_lockout, 2).failures += 1;
+        }
+        table.record("user:c", now, max_lockout, 2).failures += 1;
+        assert_eq!(table.order, vec!["user:b".to_string(), "user:c".to_string()]);
+        assert_eq!(table.records.get("user:c").map(|record| record.failures), Some(2));
+        assert!(!table.records.contains_key("user:a"));
+
+        let later = now + max_lockout;
+        table.record("user:d", later, max_lockout, 10);
+        assert_eq!(table.order, vec!["user:d".to_string()]);
+    }
+}
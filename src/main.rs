@@ -1,34 +1,79 @@
 use std::{fs, io};
+use std::io::{Seek, SeekFrom};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::io::{BufReader, ErrorKind, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use clap::Parser;
 use linked_hash_set::LinkedHashSet;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
 
-use generate_headers::{STATUS_BAD_REQUEST, STATUS_METHOD_NOT_ALLOWED, STATUS_NOT_FOUND, STATUS_OK};
+use generate_headers::{ACCEPT_RANGES, generate_connection_headers, generate_not_modified_headers, STATUS_BAD_REQUEST, STATUS_FORBIDDEN, STATUS_CONTINUE, STATUS_METHOD_NOT_ALLOWED, STATUS_NOT_FOUND, STATUS_OK,
+                       STATUS_PARTIAL_CONTENT, STATUS_PAYLOAD_TOO_LARGE, STATUS_PRECONDITION_FAILED, STATUS_RANGE_NOT_SATISFIABLE,
+                       STATUS_INTERNAL_SERVER_ERROR, STATUS_MISDIRECTED_REQUEST, STATUS_MOVED_PERMANENTLY, STATUS_PERMANENT_REDIRECT, TRANSFER_ENCODING_CHUNKED, VARY_ACCEPT_ENCODING};
 use http_server::ThreadPool;
 
-use crate::args::{HttpServerArgs, Mode, RunCommand};
-use crate::basic_auth::process_basic_auth;
-use crate::folder_operations::{build_path, is_folder, list_folder, transform_uri};
-use crate::http_parser::{BasicCredentials, decode_user_name_password, find_basic_authorization_header, Method, request_line};
-use crate::http_struct::HttpData;
-use crate::mime_type_map::{extract_extension, extract_mime_type, MimeTypeProperties, TEXT_HTML};
+use crate::args::{AuthMode, ContentCoding, HttpServerArgs, Mode, RunCommand};
+use crate::basic_auth::{attempted_username, authorize, Authorization, generate_challenges, process_basic_auth};
+use crate::charset::detect_charset;
+use crate::compression::{find_sidecar, negotiate_coding};
+use crate::cors::{find_cors_rule, generate_cors_headers, generate_preflight_headers, is_preflight};
+use crate::config_file::merge_config_file;
+use crate::conditional::{evaluate_preconditions, Precondition, Validators};
+use crate::body_reader::{body_length, BodyError, BodyLength, read_body};
+use crate::folder_operations::{build_path, check_containment, is_folder, list_folder, normalize_path, transform_uri};
+use crate::header_parser::HeaderMap;
+use crate::http_parser::{Method, Request, request, Version};
+use crate::http_struct::{ClientStream, Connection, HttpData};
+use crate::https_redirect::{https_port, redirect_location};
+use crate::ip_access::{find_ip_rule, Satisfy};
+use crate::listener::{bind, Listen, Listener};
+use crate::lockout::{locked_out, record_failure, record_success};
+use crate::mime_type_map::{extract_extension, extract_mime_type, find_mime_type_override, MimeTypeProperties, TEXT_HTML};
+use crate::range::{ByteRange, evaluate_range, multipart_boundary, multipart_end, multipart_length, multipart_part_header, RangeResponse};
+use crate::request_reader::read_request_head;
+use crate::signed_url::{read_secret, SIGNATURE_PARAM, sign_url, SignedUrl, verify_signed_url};
 use crate::string_operations::{extract_file_name, remove_double_slash, replace_slash};
+use crate::tls::build_server_config;
+use crate::virtual_host::{parse_virtual_hosts, route, Route};
 
 mod http_parser;
 mod mime_type_map;
 mod string_operations;
 mod http_struct;
 mod args;
+mod config_file;
 mod header_parser;
 mod folder_operations;
 mod basic_auth;
+mod digest_auth;
+mod bearer_auth;
+mod client_certificate;
+mod signed_url;
+mod ip_network;
+mod lockout;
+mod ip_access;
 mod generate_headers;
-
-const STATUS_METHOD_NOT_ALLOWED_RESPONSE: &'static str = "<!DOCTYPE html>
+mod https_redirect;
+mod listener;
+mod request_reader;
+mod body_reader;
+mod http_date;
+mod conditional;
+mod charset;
+mod compression;
+mod cors;
+mod acl;
+mod htpasswd;
+mod range;
+mod tls;
+mod virtual_host;
+
+const STATUS_METHOD_NOT_ALLOWED_RESPONSE: &str = "<!DOCTYPE html>
 <html lang=\"en\">
 <head>
     <meta charset=\"utf-8\">
@@ -40,179 +85,507 @@ const STATUS_METHOD_NOT_ALLOWED_RESPONSE: &'static str = "<!DOCTYPE html>
 </body>
 </html>";
 
+const STATUS_PAYLOAD_TOO_LARGE_RESPONSE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"utf-8\">
+    <title>Payload Too Large!</title>
+</head>
+<body>
+<h1>Payload too large!</h1>
+<p>413 - The request body is larger than the server is willing to process</p>
+</body>
+</html>";
+
+const STATUS_FORBIDDEN_RESPONSE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"utf-8\">
+    <title>Forbidden!</title>
+</head>
+<body>
+<h1>Forbidden!</h1>
+<p>403 - You are not allowed to access this resource</p>
+</body>
+</html>";
+
+const STATUS_MISDIRECTED_REQUEST_RESPONSE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"utf-8\">
+    <title>Misdirected Request!</title>
+</head>
+<body>
+<h1>Misdirected request!</h1>
+<p>421 - This server does not serve the requested host</p>
+</body>
+</html>";
+
+const STATUS_INTERNAL_SERVER_ERROR_RESPONSE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"utf-8\">
+    <title>Internal Server Error!</title>
+</head>
+<body>
+<h1>Internal server error!</h1>
+<p>500 - The server could not read the requested resource</p>
+</body>
+</html>";
+
 fn main() {
-    let args = HttpServerArgs::parse();
+    let config_args = merge_config_file(std::env::args_os().collect()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let args = HttpServerArgs::parse_from(config_args.args);
     let mode = args.mode;
     match mode {
         Mode::Run(run_args) => {
-            println!("Running on {} {}", run_args.host, run_args.port);
-            run_server(&run_args);
+            let virtual_hosts = parse_virtual_hosts(&run_args, config_args.virtual_hosts).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            run_server(&run_args, Arc::new(virtual_hosts));
         }
         Mode::Info(_) => {}
+        Mode::Sign(sign_args) => {
+            let secret = read_secret(&sign_args.url_secret).unwrap_or_else(|e| {
+                eprintln!("Cannot read {}: {e}", sign_args.url_secret);
+                std::process::exit(1);
+            });
+            // The server checks the signature against the normalized path
+            let path = normalize_path(&sign_args.path).unwrap_or_else(|_| {
+                eprintln!("Invalid path {}", sign_args.path);
+                std::process::exit(1);
+            });
+            let expires = unix_time() + sign_args.expires_in;
+            println!("{}{}", sign_args.base_url.trim_end_matches('/'), sign_url(&secret, &path, expires, sign_args.client_ip));
+        }
     }
 }
 
-fn run_server(run_args: &RunCommand) {
-    let listener = TcpListener::bind(format!("{}:{}",
-                                             &run_args.host,
-                                             &run_args.port)).unwrap();
+fn run_server(run_args: &RunCommand, virtual_hosts: Arc<Vec<RunCommand>>) {
+    // The TLS port and the plain port of the host come first, then the further listeners
+    let host_listens = run_args.host.iter()
+        .flat_map(|host| [(run_args.tls_port, true), (run_args.port, false)]
+            .into_iter()
+            .filter_map(|(port, tls)| port.map(|port| Listen::host(host, port, tls))));
+    let listens = host_listens.chain(run_args.listen.iter().cloned()).collect::<Vec<_>>();
+    let uses_tls = listens.iter().any(|listen| listen.tls);
+    if matches!(run_args.auth_mode, AuthMode::Certificate(_)) && !uses_tls {
+        eprintln!("Client certificates need a TLS listener, set --tls-port or a listener with ;tls");
+        std::process::exit(1);
+    }
+    if run_args.https_redirect && https_port(run_args).is_none() {
+        eprintln!("HTTPS redirects need the port of a TLS listener, set --tls-port or a listener with ;tls");
+        std::process::exit(1);
+    }
+    let tls_config = uses_tls.then(|| build_server_config(run_args).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }));
+    let listeners = listens.iter()
+        .map(|listen| bind(listen).map(|listener| (listen, listener)).unwrap_or_else(|e| {
+            eprintln!("Cannot listen on {}: {e}", listen.address);
+            std::process::exit(1);
+        }))
+        .collect::<Vec<_>>();
+    for virtual_host in virtual_hosts.iter() {
+        println!("Virtual host {} in {}", virtual_host.server_names.join(", "), virtual_host.root_folder);
+    }
     let pool = ThreadPool::new(run_args.pool_size);
 
-    for stream_result in listener.incoming() {
-        let stream = stream_result.unwrap();
-        let run_args_clone = run_args.clone();
-        pool.execute(move || {
-            handle_connection(stream, &run_args_clone);
-        });
-        println!("Connection established");
-    }
+    thread::scope(|scope| {
+        for (listen, listener) in listeners {
+            let tls_config = tls_config.clone().filter(|_| listen.tls);
+            println!("Running on {}{}", listen.address, if listen.tls { " with TLS" } else { "" });
+            let (pool, virtual_hosts) = (&pool, &virtual_hosts);
+            scope.spawn(move || accept_connections(listener, pool, run_args, virtual_hosts, tls_config));
+        }
+    });
 
     println!("Shutting down");
 
-    fn handle_connection(mut stream: TcpStream, run_args: &RunCommand) {
-        let buf_reader = BufReader::new(&mut stream);
-        let http_request: Vec<String> = buf_reader
-            .lines()
-            .map(|result| result.unwrap())
-            .take_while(|line| !line.is_empty())
-            .collect();
+    fn accept_connections(listener: Listener, pool: &ThreadPool, run_args: &RunCommand, virtual_hosts: &Arc<Vec<RunCommand>>,
+                          tls_config: Option<Arc<ServerConfig>>) {
+        loop {
+            let stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("Cannot accept connection: {:?}", e.to_string());
+                    continue;
+                }
+            };
+            // The handshake runs on the worker, so that a slow client does not hold up the listener
+            let connection = match &tls_config {
+                Some(tls_config) => match ServerConnection::new(tls_config.clone()) {
+                    Ok(tls_connection) => Connection::Tls(Box::new(StreamOwned::new(tls_connection, stream))),
+                    Err(e) => {
+                        println!("Cannot start TLS session: {e}");
+                        continue;
+                    }
+                },
+                None => Connection::Plain(stream),
+            };
+            let run_args_clone = run_args.clone();
+            let virtual_hosts = virtual_hosts.clone();
+            pool.execute(move || {
+                handle_connection(ClientStream::new(connection), &run_args_clone, &virtual_hosts);
+            });
+            println!("Connection established");
+        }
+    }
 
-        if http_request.is_empty() {
-            send_bad_request(&mut stream, &run_args.root_folder);
+    fn handle_connection(mut stream: ClientStream, default_host: &RunCommand, virtual_hosts: &[RunCommand]) {
+        let run_args = default_host;
+        let keep_alive_timeout = Duration::from_secs(run_args.keep_alive_timeout);
+        let timeouts = stream.socket().set_read_timeout(Some(keep_alive_timeout))
+            .and_then(|_| stream.socket().set_write_timeout(Some(Duration::from_secs(run_args.write_timeout))));
+        if let Err(e) = timeouts {
+            println!("Cannot set timeouts: {:?}", e.to_string());
             return;
         }
-        let rl = http_request[0].clone();
-        let (_, request_line_option) = request_line(rl.as_bytes()).unwrap();
-
-        for header in http_request.iter() {
-            println!(":: {:#?}", header);
+        if let Err(e) = stream.complete_handshake() {
+            println!("TLS handshake failed: {:?}", e.to_string());
+            return;
         }
+        if let Some(tls_connection) = stream.tls_connection() {
+            println!("TLS {:?} server name {:?} protocol {:?}", tls_connection.protocol_version(), tls_connection.server_name(),
+                     tls_connection.alpn_protocol().map(String::from_utf8_lossy));
+        }
+        let max_requests = run_args.max_requests.max(1);
+        for request_count in 1..=max_requests {
+            let head_result = read_request_head(&mut stream.reader_until(Instant::now() + keep_alive_timeout));
+            // The body is read with the timeout applying to each read again
+            if let Err(e) = stream.socket().set_read_timeout(Some(keep_alive_timeout)) {
+                println!("Cannot set read timeout: {:?}", e.to_string());
+                return;
+            }
+            let head = match head_result {
+                Ok(head) if !head.is_empty() => head,
+                Ok(_) => return,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    println!("Closing idle connection");
+                    return;
+                }
+                Err(e) => {
+                    println!("Cannot read request: {:?}", e.to_string());
+                    reset_response_headers(&mut stream, run_args, false, 0);
+                    send_bad_request(&mut stream, run_args);
+                    return;
+                }
+            };
+
+            let mut http_request = match request(&head) {
+                Ok((_, Some(http_request)))
+                if http_request.request_line.version == Version::V10 || http_request.headers.contains("Host") => http_request,
+                _ => {
+                    reset_response_headers(&mut stream, run_args, false, 0);
+                    send_bad_request(&mut stream, run_args);
+                    return;
+                }
+            };
+
+            // An absolute request target wins over the Host header
+            let host = http_request.request_line.uri.authority.as_deref().or(http_request.headers.get("Host"));
+            let server_name = stream.tls_connection().and_then(|tls_connection| tls_connection.server_name()).map(String::from);
+            let run_args = match route(default_host, virtual_hosts, host, server_name.as_deref()) {
+                Route::Serve(run_args) => run_args,
+                Route::Misdirected => {
+                    println!("No host for {:?} on server name {:?}", host, server_name);
+                    reset_response_headers(&mut stream, default_host, false, 0);
+                    send_error_response(HttpData {
+                        stream: &mut stream,
+                        uri: "".to_string(),
+                        mime_type_map: &MimeTypeProperties::default_extension(),
+                        is_head: &(http_request.request_line.method == Method::Head),
+                        root_folder: &default_host.root_folder,
+                        request_headers: &http_request.headers,
+                        version: http_request.request_line.version,
+                        run_args: default_host,
+                    }, "misdirected_request.html", STATUS_MISDIRECTED_REQUEST, STATUS_MISDIRECTED_REQUEST_RESPONSE);
+                    return;
+                }
+                Route::BadRequest => {
+                    reset_response_headers(&mut stream, default_host, false, 0);
+                    send_bad_request(&mut stream, default_host);
+                    return;
+                }
+            };
 
-        let root_folder = &run_args.root_folder;
+            println!("{} {}", http_request.request_line.method, http_request.request_line.uri);
+            for (name, values) in http_request.headers.iter() {
+                println!(":: {name}: {:?}", values);
+            }
 
-        match request_line_option {
-            Some(request_line_content) => {
-                let uri = request_line_content.uri.clone();
-                if let Some(use_basic_auth) = process_basic_auth(&uri, run_args) {
-                    let credentials_option = process_basic_authentication(http_request);
-                    if let None = credentials_option {
-                        stream_headers_only(&mut stream,
-                                            generate_headers::generate_authenticate_response);
-                        return;
-                    }
-                    let credentials = credentials_option.unwrap();
-                    if credentials.username != *use_basic_auth.username && credentials.password != *use_basic_auth.password {
-                        stream_headers_only(&mut stream,
-                                            generate_headers::generate_authenticate_response);
-                        return;
-                    }
-                }
-                match request_line_content.method {
-                    Method::Get | Method::Head => {
-                        let built_path = transform_uri(uri.clone(), root_folder);
-                        let extension_option = extract_extension(built_path.as_str());
-                        let folder_option = is_folder(built_path.clone());
-                        let mime_type_map = extract_mime_type(extension_option);
-                        println!("Requested resource: {:#?}. Mime type: {}", built_path.clone(), mime_type_map.content_type);
-                        let is_head = request_line_content.method == Method::Head;
-                        let http_data = HttpData {
-                            stream: &mut stream,
-                            uri: built_path,
-                            mime_type_map: &mime_type_map,
-                            is_head: &is_head,
-                            root_folder: root_folder,
-                        };
-                        match folder_option {
-                            Some(folder) => {
-                                process_folder_response(http_data, folder);
-                            }
-                            None => {
-                                if mime_type_map.binary {
-                                    process_binary_content(http_data);
-                                } else {
-                                    process_text_content(http_data);
-                                }
-                            }
-                        }
-                    }
-                    Method::Options => {
-                        let uri = replace_slash(request_line_content.uri);
-                        println!("Requested resource: {:#?}", uri);
-                        stream_headers_only(&mut stream,
-                                            generate_headers::generate_option_headers);
-                    }
-                    _ => {
-                        send_error_response(HttpData {
-                            stream: &mut stream,
-                            uri: "".to_string(),
-                            mime_type_map: &MimeTypeProperties::default_extension(),
-                            is_head: &false,
-                            root_folder: &run_args.root_folder,
-                        }, "method_not_allowed.html", STATUS_METHOD_NOT_ALLOWED,
-                                            STATUS_METHOD_NOT_ALLOWED_RESPONSE);
-                    }
+            if let Err(e) = process_body(&mut stream, &mut http_request, run_args) {
+                println!("{e}");
+                reset_response_headers(&mut stream, run_args, false, 0);
+                match e {
+                    BodyError::TooLarge => send_payload_too_large(&mut stream, run_args),
+                    BodyError::Malformed(_) => send_bad_request(&mut stream, run_args),
+                    BodyError::Io(_) => {}
                 }
+                return;
             }
-            None => {
-                send_bad_request(&mut stream, &run_args.root_folder);
+
+            let keep_alive = http_request.keep_alive() && request_count < max_requests;
+            reset_response_headers(&mut stream, run_args, keep_alive, max_requests - request_count);
+            if run_args.https_redirect && stream.tls_connection().is_none() && stream.socket().is_tcp() {
+                redirect_to_https(&mut stream, &http_request, run_args);
+            } else {
+                handle_request(&mut stream, http_request, run_args);
+            }
+            if !keep_alive {
+                return;
             }
         }
     }
 
-    fn process_basic_authentication(http_request: Vec<String>) -> Option<BasicCredentials> {
-        let authentication_option = find_basic_authorization_header(http_request);
-        match authentication_option {
-            Some(authentication) => {
-                let credentials_option = decode_user_name_password(&authentication);
-                match credentials_option {
-                    Some(credentials) => {
-                        return Some(credentials);
-                    }
-                    None => {}
-                }
+    /// The headers of every response on the connection: its state, the configured headers and, when it is encrypted,
+    /// the policy telling browsers to stay on HTTPS
+    fn reset_response_headers(stream: &mut ClientStream, run_args: &RunCommand, keep_alive: bool, max: usize) {
+        stream.response_headers = generate_connection_headers(keep_alive, run_args.keep_alive_timeout, max);
+        for header in &run_args.headers {
+            stream.response_headers.insert(format!("{header}\r\n"));
+        }
+        if let (Some(max_age), Some(_)) = (run_args.hsts_max_age, stream.tls_connection()) {
+            stream.response_headers.insert(generate_headers::generate_strict_transport_security(
+                max_age, run_args.hsts_include_subdomains, run_args.hsts_preload));
+        }
+    }
+
+    /// Sends the client to the same URL on the TLS port. GET and HEAD get a 301, other methods a 308,
+    /// so that the client repeats them with the same method and body.
+    fn redirect_to_https(stream: &mut ClientStream, http_request: &Request, run_args: &RunCommand) {
+        let request_line = &http_request.request_line;
+        let local_address = stream.socket().local_ip().map(|ip| match ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{ip}]"),
+        });
+        // An absolute request target wins over the Host header, HTTP/1.0 clients may send neither
+        let host = request_line.uri.authority.as_deref()
+            .or(http_request.headers.get("Host"))
+            .or(local_address.as_deref());
+        let tls_port = https_port(run_args).expect("Redirects need a TLS listener");
+        match host.and_then(|host| redirect_location(&request_line.uri, host, tls_port)) {
+            Some(location) => {
+                println!("Redirecting to {location}");
+                let status_line = match request_line.method {
+                    Method::Get | Method::Head => STATUS_MOVED_PERMANENTLY,
+                    _ => STATUS_PERMANENT_REDIRECT
+                };
+                send_headers(stream, generate_headers::generate_redirect_response(status_line, &location));
             }
-            None => {}
+            None => send_bad_request(stream, run_args)
         }
-        None
     }
-}
 
-fn process_text_content(http_data: HttpData) {
-    let HttpData {
-        stream,
-        uri,
-        mime_type_map,
-        is_head,
-        root_folder
-    } = http_data;
-    let path = uri.clone();
-    let result_file = File::open(path);
-    match result_file {
-        Ok(path) => {
-            let res = io::read_to_string(BufReader::new(path));
-            match res {
-                Ok(contents) => {
-                    stream_text(stream,
-                                STATUS_OK,
-                                contents.as_str(), mime_type_map.content_type.as_str(),
-                                is_head);
+    /// Reads the request body, answering "Expect: 100-continue" before the client sends it
+    fn process_body(stream: &mut ClientStream, http_request: &mut Request, run_args: &RunCommand) -> Result<(), BodyError> {
+        let body_length = body_length(&http_request.headers)?;
+        if body_length == BodyLength::Empty {
+            return Ok(());
+        }
+        if let BodyLength::Fixed(length) = body_length {
+            if length > run_args.max_body_size {
+                return Err(BodyError::TooLarge);
+            }
+        }
+        if http_request.request_line.version == Version::V11 && http_request.headers.has_token("Expect", "100-continue") {
+            stream.write_all(format!("{STATUS_CONTINUE}\r\n\r\n").as_bytes())?;
+        }
+        http_request.body = read_body(stream.reader(), &body_length, run_args.max_body_size)?;
+        println!("Request body: {} bytes", http_request.body.content.len());
+        for (name, values) in http_request.body.trailers.iter() {
+            println!(":: {name}: {:?}", values);
+        }
+        Ok(())
+    }
+
+    fn handle_request(stream: &mut ClientStream, http_request: Request, run_args: &RunCommand) {
+        let root_folder = &run_args.root_folder;
+        let request_line_content = http_request.request_line.clone();
+        // Authentication and file lookup both work on the normalized path, so dot segments cannot bypass them
+        let uri = match normalize_path(&request_line_content.uri.path) {
+            Ok(uri) => uri,
+            Err(_) => {
+                send_bad_request(stream, run_args);
+                return;
+            }
+        };
+        // Preflight requests never carry credentials, so they are answered before authentication
+        if let Some(cors_rule) = find_cors_rule(&uri, &run_args.cors) {
+            if request_line_content.method == Method::Options && is_preflight(&http_request.headers) {
+                println!("CORS preflight for {uri}");
+                stream.response_headers.extend(generate_preflight_headers(cors_rule, &http_request.headers));
+                stream_headers_only(stream, generate_headers::generate_option_headers);
+                return;
+            }
+            stream.response_headers.extend(generate_cors_headers(cors_rule, &http_request.headers));
+        }
+        let peer_ip = stream.socket().peer_ip();
+        // A valid signed URL stands in for the credentials of GET and HEAD requests
+        let is_get_or_head = matches!(request_line_content.method, Method::Get | Method::Head);
+        let signed_url = match &run_args.url_secret {
+            Some(url_secret) if is_get_or_head && request_line_content.uri.query_params.contains_key(SIGNATURE_PARAM) => {
+                let verification = read_secret(url_secret)
+                    .inspect_err(|e| println!("Cannot read {url_secret}: {:?}", e.to_string()))
+                    .map(|secret| verify_signed_url(&secret, &uri, &request_line_content.uri.query_params, peer_ip, unix_time()))
+                    .unwrap_or(SignedUrl::Invalid);
+                println!("Signed URL for {uri}: {:?}", verification);
+                Some(verification)
+            }
+            _ => None
+        };
+        let signed_url_valid = signed_url == Some(SignedUrl::Valid);
+        // The address rule of the path applies regardless of the credentials. With satisfy=any an allowed address
+        // replaces them, otherwise they are needed, so a path without protected folders stays closed.
+        let ip_access = find_ip_rule(&uri, &run_args.ip_rules).map(|rule| (rule.satisfy, rule.allows(peer_ip)));
+        let protection = match ip_access {
+            _ if signed_url_valid => None,
+            Some((Satisfy::Any, true)) => None,
+            _ => process_basic_auth(&uri, run_args)
+        };
+        let ip_denied = match ip_access {
+            Some((Satisfy::All, false)) => true,
+            Some((Satisfy::Any, false)) => protection.is_none() && !signed_url_valid,
+            _ => false
+        };
+        if ip_denied || matches!(signed_url, Some(SignedUrl::Expired | SignedUrl::Invalid)) {
+            if ip_denied {
+                println!("Address {:?} is not allowed for {uri}", peer_ip);
+            }
+            forbidden(HttpData {
+                stream,
+                uri,
+                mime_type_map: &MimeTypeProperties::default_extension(),
+                is_head: &(request_line_content.method == Method::Head),
+                root_folder,
+                request_headers: &http_request.headers,
+                version: request_line_content.version,
+                run_args,
+            });
+            return;
+        }
+        if let Some(protection) = protection {
+            let username = attempted_username(&protection, &http_request.headers);
+            if let Some(retry_after) = locked_out(run_args, peer_ip, username.as_deref()) {
+                println!("Locked out, retry after {:?}", retry_after);
+                let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                send_headers(stream, generate_headers::generate_too_many_requests_response(retry_after));
+                return;
+            }
+            let client_certificate = stream.tls_connection()
+                .and_then(|tls_connection| tls_connection.peer_certificates())
+                .and_then(|chain| chain.first())
+                .map(|certificate| certificate.clone().into_owned());
+            match authorize(&protection, &http_request.headers, &request_line_content.method.to_string(),
+                            &request_line_content.uri, client_certificate.as_ref()) {
+                Authorization::Granted => record_success(username.as_deref()),
+                authorization @ (Authorization::Unauthorized | Authorization::Stale | Authorization::InvalidToken(_)) => {
+                    // A request without credentials only asks for the challenge, and a stale nonce is no wrong password
+                    if http_request.headers.contains("Authorization") && authorization != Authorization::Stale {
+                        record_failure(run_args, peer_ip, username.as_deref(), &uri);
+                    }
+                    send_headers(stream, generate_headers::generate_authenticate_response(&generate_challenges(&protection, &authorization)));
+                    return;
                 }
-                Err(e) => {
-                    println!("Error: {:?}", e.to_string());
-                    not_found(HttpData { stream, uri: uri.clone(), mime_type_map, is_head, root_folder });
+                authorization @ (Authorization::Forbidden | Authorization::InsufficientScope) => {
+                    if authorization == Authorization::InsufficientScope {
+                        for challenge in generate_challenges(&protection, &authorization) {
+                            stream.response_headers.insert(format!("WWW-Authenticate: {challenge}\r\n"));
+                        }
+                    }
+                    forbidden(HttpData {
+                        stream,
+                        uri,
+                        mime_type_map: &MimeTypeProperties::default_extension(),
+                        is_head: &(request_line_content.method == Method::Head),
+                        root_folder,
+                        request_headers: &http_request.headers,
+                        version: request_line_content.version,
+                        run_args,
+                    });
+                    return;
                 }
             }
         }
-        Err(_) => {
-            not_found(HttpData { stream, uri: uri.clone(), mime_type_map, is_head, root_folder });
+        match request_line_content.method {
+            Method::Get | Method::Head => {
+                let is_head = request_line_content.method == Method::Head;
+                let built_path = match transform_uri(uri.clone(), root_folder, &run_args.index, &run_args.symlinks) {
+                    Ok(built_path) => built_path,
+                    Err(_) => {
+                        forbidden(HttpData {
+                            stream,
+                            uri,
+                            mime_type_map: &MimeTypeProperties::default_extension(),
+                            is_head: &is_head,
+                            root_folder,
+                            request_headers: &http_request.headers,
+                            version: request_line_content.version,
+                            run_args,
+                        });
+                        return;
+                    }
+                };
+                let extension_option = extract_extension(built_path.as_str());
+                let folder_option = is_folder(built_path.clone());
+                let mime_type_map = find_mime_type_override(&run_args.mime_types, extension_option.as_deref())
+                    .unwrap_or_else(|| extract_mime_type(extension_option));
+                println!("Requested resource: {:#?}. Mime type: {}", built_path.clone(), mime_type_map.content_type);
+                let http_data = HttpData {
+                    stream,
+                    uri: built_path,
+                    mime_type_map: &mime_type_map,
+                    is_head: &is_head,
+                    root_folder,
+                    request_headers: &http_request.headers,
+                    version: request_line_content.version,
+                    run_args,
+                };
+                match folder_option {
+                    Some(folder) => {
+                        process_folder_response(http_data, folder);
+                    }
+                    None => {
+                        process_file_content(http_data);
+                    }
+                }
+            }
+            Method::Options => {
+                let uri = replace_slash(uri);
+                println!("Requested resource: {:#?}", uri);
+                stream_headers_only(stream,
+                                    generate_headers::generate_option_headers);
+            }
+            _ => {
+                send_error_response(HttpData {
+                    stream,
+                    uri: "".to_string(),
+                    mime_type_map: &MimeTypeProperties::default_extension(),
+                    is_head: &false,
+                    root_folder: &run_args.root_folder,
+                    request_headers: &http_request.headers,
+                    version: request_line_content.version,
+                    run_args,
+                }, "method_not_allowed.html", STATUS_METHOD_NOT_ALLOWED,
+                                    STATUS_METHOD_NOT_ALLOWED_RESPONSE);
+            }
         }
     }
 }
 
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
 fn process_folder_response(http_data: HttpData, dir: PathBuf) {
     let stream = http_data.stream;
     let is_head = http_data.is_head;
-    let folder_response = list_folder(dir, &http_data.root_folder);
+    let folder_response = list_folder(dir, http_data.root_folder);
     stream_text(stream,
                 STATUS_OK,
                 folder_response.as_str(), TEXT_HTML,
@@ -221,9 +594,8 @@ fn process_folder_response(http_data: HttpData, dir: PathBuf) {
 
 fn send_error_response(http_data: HttpData, html_file: &str,
                        status: &str, missing_html: &str) {
-    let HttpData { stream, is_head, .. } = http_data;
-    let request_file = format!("./{}/{html_file}", http_data.root_folder);
-    let result_file = File::open(request_file);
+    let HttpData { stream, is_head, run_args, .. } = http_data;
+    let result_file = File::open(error_page_path(run_args, html_file));
     match result_file {
         Ok(file) => {
             let buf_result = io::read_to_string(BufReader::new(file));
@@ -244,6 +616,20 @@ fn send_error_response(http_data: HttpData, html_file: &str,
     }
 }
 
+/// The file of an error page, in the folder of the error pages or else in the root folder
+fn error_page_path(run_args: &RunCommand, html_file: &str) -> String {
+    build_path(html_file.to_string(), run_args.error_pages.as_ref().unwrap_or(&run_args.root_folder))
+}
+
+fn forbidden(http_data: HttpData) {
+    send_error_response(http_data, "forbidden.html", STATUS_FORBIDDEN, STATUS_FORBIDDEN_RESPONSE);
+}
+
+fn internal_server_error(http_data: HttpData) {
+    send_error_response(http_data, "internal_server_error.html", STATUS_INTERNAL_SERVER_ERROR,
+                        STATUS_INTERNAL_SERVER_ERROR_RESPONSE);
+}
+
 fn not_found(http_data: HttpData) {
     let uri = http_data.uri.clone();
     send_error_response(http_data, "not_found.html", STATUS_NOT_FOUND,
@@ -260,53 +646,189 @@ fn not_found(http_data: HttpData) {
 </html>", uri).as_str());
 }
 
-fn process_binary_content(http_data: HttpData) {
+/// Text and binary files are both streamed from disk without loading them into memory
+fn process_file_content(http_data: HttpData) {
+    let res = File::open(http_data.uri.clone())
+        .and_then(|file| file.metadata().map(|metadata| (file, metadata)));
+
+    match res {
+        Ok((mut file, metadata)) => {
+            let mime_type_properties = http_data.mime_type_map;
+            let charset_result = if mime_type_properties.binary { Ok(None) } else {
+                detect_charset(&mut file, http_data.run_args.default_charset.as_deref())
+            };
+            match charset_result {
+                Ok(charset) => {
+                    let content_type = generate_content_type(&mime_type_properties.content_type, charset.as_deref());
+                    let result = match open_sidecar(&http_data) {
+                        Some((coding, sidecar_path, mut sidecar, sidecar_metadata)) => {
+                            let validators = Validators::from_metadata(&sidecar_path, &sidecar_metadata, &http_data.run_args.etag);
+                            stream_content(http_data, &mut sidecar, &content_type, sidecar_metadata.len(), &validators, Some(coding))
+                        }
+                        None => {
+                            let validators = Validators::from_metadata(&http_data.uri, &metadata, &http_data.run_args.etag);
+                            stream_content(http_data, &mut file, &content_type, metadata.len(), &validators, None)
+                        }
+                    };
+                    if let Err(e) = result {
+                        println!("Error: {:?}", e.to_string());
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {:?}", e.to_string());
+                    internal_server_error(http_data);
+                }
+            }
+        }
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::InvalidFilename | ErrorKind::InvalidInput) => {
+            not_found(http_data);
+        }
+        Err(e) => {
+            println!("Error: {:?}", e.to_string());
+            internal_server_error(http_data);
+        }
+    }
+}
+
+/// Opens the precompressed sidecar of the requested file, which has to obey the same symlink policy
+fn open_sidecar(http_data: &HttpData) -> Option<(ContentCoding, String, File, fs::Metadata)> {
+    let run_args = http_data.run_args;
+    if run_args.no_compression {
+        return None;
+    }
+    let (coding, sidecar_path) = find_sidecar(&http_data.uri, http_data.request_headers, &run_args.compression)?;
+    check_containment(Path::new(&sidecar_path), http_data.root_folder, &run_args.symlinks).ok()?;
+    let sidecar = File::open(&sidecar_path)
+        .inspect_err(|e| println!("Cannot open {sidecar_path}: {:?}", e.to_string()))
+        .ok()?;
+    let metadata = sidecar.metadata().ok()?;
+    println!("Serving precompressed {sidecar_path}");
+    Some((coding, sidecar_path, sidecar, metadata))
+}
+
+/// Writes the file content after evaluating the preconditions, only the byte ranges of it requested
+/// by a GET request, or the content compressed with the coding negotiated from Accept-Encoding.
+/// A precompressed file is sent as it is, its ranges refer to the compressed content.
+fn stream_content(http_data: HttpData, file: &mut File, content_type: &str, length: u64,
+                  identity_validators: &Validators, precompressed: Option<ContentCoding>) -> io::Result<()> {
     let HttpData {
         stream,
         uri,
         mime_type_map: mime_type_properties,
         is_head,
-        root_folder
+        request_headers,
+        version,
+        run_args,
+        ..
     } = http_data;
-    let res = fs::read(uri.clone());
-    let mime_type = &mime_type_properties.content_type;
+    // The content type already carries the detected charset, so the headers are generated as binary
+    let is_binary = &true;
+
+    // The ranges of a precompressed file refer to the sidecar, so If-Range has to match the entity tag sent for it
+    let range_validators = identity_validators.for_representation(precompressed);
+    let range_response = if *is_head { RangeResponse::Full } else { evaluate_range(request_headers, length, &range_validators) };
+    // Byte ranges refer to the uncompressed content, so only full responses are compressed.
+    // Chunked framing is needed for the unknown compressed length, which HTTP/1.0 does not support.
+    let varies = precompressed.is_some()
+        || (!run_args.no_compression && mime_type_properties.compressible() && length >= run_args.compression_min_size);
+    let coding = if varies && precompressed.is_none() && version == Version::V11 && range_response == RangeResponse::Full {
+        negotiate_coding(request_headers, &run_args.compression)
+    } else { None };
+    let validators = identity_validators.for_representation(precompressed.or(coding));
+    let mut representation_headers = validators.headers();
+    if varies {
+        representation_headers.insert(VARY_ACCEPT_ENCODING.to_string());
+    }
 
-    match res {
-        Ok(content) => {
-            let bytes = &content[..];
-            let length = bytes.len();
-            let header_map =
-                generate_status_headers(STATUS_OK, length, mime_type.as_str(), &mime_type_properties.binary);
-            let response = generate_binary_status_line(
-                uri.clone(),
-                header_map,
-                mime_type_properties,
-            );
-            let header_bytes = response.as_bytes();
-            if *is_head {
-                stream.write_all(header_bytes).unwrap();
-            } else {
-                let concat_vec = [header_bytes, bytes].concat();
-                let concat_bytes = &concat_vec[..];
-                stream.write_all(concat_bytes).unwrap();
+    match evaluate_preconditions(request_headers, &validators, true) {
+        Precondition::NotModified => {
+            let mut header_map = generate_not_modified_headers();
+            header_map.extend(representation_headers);
+            let response = format!("{}\r\n", concatenate_headers(&header_map, &stream.response_headers));
+            return stream.write_all(response.as_bytes());
+        }
+        Precondition::Failed => {
+            let header_map = generate_status_headers(STATUS_PRECONDITION_FAILED, 0, content_type, is_binary);
+            let response = format!("{}\r\n", concatenate_headers(&header_map, &stream.response_headers));
+            return stream.write_all(response.as_bytes());
+        }
+        Precondition::Proceed => {}
+    }
+    if let Some(precompressed) = precompressed {
+        representation_headers.insert(format!("Content-Encoding: {}\r\n", precompressed.token()));
+    }
+
+    match (range_response, coding) {
+        (RangeResponse::Full, Some(coding)) => {
+            let mut header_map = generate_chunked_status_headers(STATUS_OK, content_type);
+            header_map.insert(format!("Content-Encoding: {}\r\n", coding.token()));
+            header_map.extend(representation_headers);
+            let response = generate_binary_status_line(uri, header_map, &stream.response_headers, mime_type_properties);
+            stream.write_all(response.as_bytes())?;
+            if !*is_head {
+                stream.copy_file_compressed(file, length, coding)?;
             }
         }
-        Err(_) => {
-            not_found(HttpData {
-                stream,
-                uri: uri.clone(),
-                mime_type_map: mime_type_properties,
-                is_head,
-                root_folder,
-            });
+        (RangeResponse::Full, None) => {
+            let mut header_map =
+                generate_status_headers(STATUS_OK, length as usize, content_type, is_binary);
+            header_map.insert(ACCEPT_RANGES.to_string());
+            header_map.extend(representation_headers);
+            let response = generate_binary_status_line(uri, header_map, &stream.response_headers, mime_type_properties);
+            stream.write_all(response.as_bytes())?;
+            if !*is_head {
+                stream.copy_file(file, length)?;
+            }
+        }
+        (RangeResponse::Partial(ranges), _) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let mut header_map =
+                generate_status_headers(STATUS_PARTIAL_CONTENT, range.len() as usize, content_type, is_binary);
+            header_map.insert(ACCEPT_RANGES.to_string());
+            header_map.extend(representation_headers);
+            header_map.insert(format!("Content-Range: {}\r\n", range.content_range(length)));
+            let response = generate_binary_status_line(uri, header_map, &stream.response_headers, mime_type_properties);
+            stream.write_all(response.as_bytes())?;
+            copy_range(file, stream, &range)?;
+        }
+        (RangeResponse::Partial(ranges), _) => {
+            let boundary = multipart_boundary();
+            let multipart_length = multipart_length(&boundary, content_type, &ranges, length);
+            let mut header_map = generate_status_headers(STATUS_PARTIAL_CONTENT, multipart_length as usize,
+                                                         format!("multipart/byteranges; boundary={boundary}").as_str(), is_binary);
+            header_map.insert(ACCEPT_RANGES.to_string());
+            header_map.extend(representation_headers);
+            let response = generate_binary_status_line(uri, header_map, &stream.response_headers, mime_type_properties);
+            stream.write_all(response.as_bytes())?;
+            for range in ranges.iter() {
+                stream.write_all(multipart_part_header(&boundary, content_type, range, length).as_bytes())?;
+                copy_range(file, stream, range)?;
+            }
+            stream.write_all(multipart_end(&boundary).as_bytes())?;
+        }
+        (RangeResponse::Unsatisfiable, _) => {
+            let mut header_map =
+                generate_status_headers(STATUS_RANGE_NOT_SATISFIABLE, 0, content_type, is_binary);
+            header_map.insert(ACCEPT_RANGES.to_string());
+            header_map.insert(format!("Content-Range: bytes */{length}\r\n"));
+            let response = format!("{}\r\n", concatenate_headers(&header_map, &stream.response_headers));
+            stream.write_all(response.as_bytes())?;
         }
     }
+    Ok(())
+}
+
+fn copy_range(file: &mut File, stream: &mut ClientStream, range: &ByteRange) -> io::Result<()> {
+    file.seek(SeekFrom::Start(range.start))?;
+    stream.copy_file(file, range.len())?;
+    Ok(())
 }
 
 fn generate_binary_status_line(uri: String, header_map: LinkedHashSet<String>,
+                               response_headers: &LinkedHashSet<String>,
                                mime_type_properties: &MimeTypeProperties) -> String {
     let attachment = &mime_type_properties.attachment;
-    let concatenated_headers_str = concatenate_headers(&header_map);
+    let concatenated_headers_str = concatenate_headers(&header_map, response_headers);
     if *attachment {
         let file_name = extract_file_name(uri);
         let content_disposition = format!("Content-Disposition: attachment; filename=\"{file_name}\"\r\n");
@@ -317,12 +839,25 @@ fn generate_binary_status_line(uri: String, header_map: LinkedHashSet<String>,
 }
 
 
-fn send_bad_request(mut stream: &mut TcpStream, root_folder: &String) {
-    let bad_request = build_path("./{}/bad_request.html".to_string(), root_folder);
-    let result = fs::read_to_string(bad_request.as_str());
+fn send_payload_too_large(stream: &mut ClientStream, run_args: &RunCommand) {
+    send_error_response(HttpData {
+        stream,
+        uri: "".to_string(),
+        mime_type_map: &MimeTypeProperties::default_extension(),
+        is_head: &false,
+        root_folder: &run_args.root_folder,
+        request_headers: &HeaderMap::new(),
+        version: Version::V11,
+        run_args,
+    }, "payload_too_large.html", STATUS_PAYLOAD_TOO_LARGE,
+                        STATUS_PAYLOAD_TOO_LARGE_RESPONSE);
+}
+
+fn send_bad_request(stream: &mut ClientStream, run_args: &RunCommand) {
+    let result = fs::read_to_string(error_page_path(run_args, "bad_request.html"));
     match result {
         Ok(contents) => {
-            stream_text(&mut stream, STATUS_BAD_REQUEST, contents.as_str(), TEXT_HTML, &false);
+            stream_text(stream, STATUS_BAD_REQUEST, contents.as_str(), TEXT_HTML, &false);
         }
         Err(_) => {
             let contents = "<!DOCTYPE html>
@@ -336,12 +871,12 @@ fn send_bad_request(mut stream: &mut TcpStream, root_folder: &String) {
 <p>400 - Your request could not be understood by the server</p>
 </body>
 </html>";
-            stream_text(&mut stream, STATUS_BAD_REQUEST, contents, TEXT_HTML, &false);
+            stream_text(stream, STATUS_BAD_REQUEST, contents, TEXT_HTML, &false);
         }
     }
 }
 
-fn stream_text(stream: &mut TcpStream,
+fn stream_text(stream: &mut ClientStream,
                status_line: &str,
                contents: &str,
                mime_type: &str,
@@ -350,7 +885,7 @@ fn stream_text(stream: &mut TcpStream,
     stream_text_function(stream, status_line, contents, mime_type, is_head, generate_status_headers);
 }
 
-fn stream_headers_only(stream: &mut TcpStream,
+fn stream_headers_only(stream: &mut ClientStream,
                        generate_status_headers: fn(status_line: &str, length: usize,
                                                    mime_type: &str,
                                                    is_binary: &bool) -> LinkedHashSet<String>) {
@@ -358,7 +893,14 @@ fn stream_headers_only(stream: &mut TcpStream,
                          &true, generate_status_headers)
 }
 
-fn stream_text_function(stream: &mut TcpStream,
+fn send_headers(stream: &mut ClientStream, header_map: LinkedHashSet<String>) {
+    let response = format!("{}\r\n", concatenate_headers(&header_map, &stream.response_headers));
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        println!("Error: {:?}", e.to_string());
+    }
+}
+
+fn stream_text_function(stream: &mut ClientStream,
                         status_line: &str,
                         contents: &str,
                         mime_type: &str,
@@ -368,24 +910,48 @@ fn stream_text_function(stream: &mut TcpStream,
                                                     is_binary: &bool) -> LinkedHashSet<String>,
 ) {
     let length = contents.len();
-    let header_map = generate_status_headers(status_line, length, mime_type, is_head);
+    let header_map = generate_status_headers(status_line, length, mime_type, &false);
 
-    let concatenated_headers_str = concatenate_headers(&header_map);
+    let concatenated_headers_str = concatenate_headers(&header_map, &stream.response_headers);
 
     let response = if *is_head { format!("{concatenated_headers_str}\r\n") } else { format!("{concatenated_headers_str}\r\n{contents}") };
     let bytes = response.as_bytes();
-    stream.write_all(bytes).unwrap();
+    if let Err(e) = stream.write_all(bytes) {
+        println!("Error: {:?}", e.to_string());
+    }
 }
 
-fn concatenate_headers(header_map: &LinkedHashSet<String>) -> String {
-    return header_map.iter()
+fn concatenate_headers(header_map: &LinkedHashSet<String>, response_headers: &LinkedHashSet<String>) -> String {
+    header_map.iter()
+        .chain(response_headers.iter())
         .map(|x| (*x).to_string())
-        .collect::<Vec<_>>().join("");
+        .collect::<Vec<_>>().join("")
+}
+
+fn generate_content_type(mime_type: &str, charset: Option<&str>) -> String {
+    match charset {
+        Some(charset) => format!("{mime_type}; charset={charset}"),
+        None => mime_type.to_string()
+    }
+}
+
+/// Headers of a response whose length is unknown in advance, so its content is sent in chunks
+fn generate_chunked_status_headers(status_line: &str, content_type: &str) -> LinkedHashSet<String> {
+    let (status_line, cache_control, server) = generate_headers::generate_status_with_common_headers(status_line);
+
+    let mut status_headers_set = LinkedHashSet::new();
+    status_headers_set.insert(status_line);
+    status_headers_set.insert(TRANSFER_ENCODING_CHUNKED.to_string());
+    status_headers_set.insert(format!("Content-Type: {content_type}\r\n"));
+    status_headers_set.insert(cache_control);
+    status_headers_set.insert(server);
+    status_headers_set
 }
 
 fn generate_status_headers(status_line: &str, length: usize, mime_type: &str, is_binary: &bool) -> LinkedHashSet<String> {
     let content_length = format!("Content-Length: {length}\r\n");
-    let content_type = if *is_binary { format!("Content-Type: {mime_type}\r\n") } else { format!("Content-Type: {mime_type}; charset=utf-8\r\n") };
+    let charset = if *is_binary { None } else { Some("utf-8") };
+    let content_type = format!("Content-Type: {}\r\n", generate_content_type(mime_type, charset));
     let (status_line, cache_control, server) = generate_headers::generate_status_with_common_headers(status_line);
 
     let mut status_headers_set = LinkedHashSet::new();
@@ -395,5 +961,5 @@ fn generate_status_headers(status_line: &str, length: usize, mime_type: &str, is
     status_headers_set.insert(cache_control);
     status_headers_set.insert(server);
 
-    return status_headers_set.clone();
+    status_headers_set.clone()
 }
@@ -2,11 +2,23 @@ use std::str::from_utf8;
 
 use phf::{Map, phf_map};
 
-pub(crate) const TEXT_HTML: &'static str = "text/html";
-pub(crate) const JPEG: &'static str = "image/jpeg";
+pub(crate) const TEXT_HTML: &str = "text/html";
+pub(crate) const JPEG: &str = "image/jpeg";
+
+/// Binary formats which are not compressed already
+const COMPRESSIBLE_TYPES: [&str; 8] = [
+    "application/javascript",
+    "application/json",
+    "application/wasm",
+    "application/xml",
+    "font/otf",
+    "font/ttf",
+    "image/bmp",
+    "image/x-icon",
+];
 
 // mime type, is binary, is attachment
-static MIME_TYPES: Map<&'static str, (&'static str, bool, bool)> = phf_map! {
+static MIME_TYPES: Map<&str, (&str, bool, bool)> = phf_map! {
 
     "css" => ("text/css", false, false),
     "doc" => ("application/msword", true, true),
@@ -1014,11 +1026,18 @@ impl MimeTypeProperties {
     pub(crate) fn new(content_type: &str, binary: bool, attachment: bool) -> MimeTypeProperties {
         MimeTypeProperties {
             content_type: content_type.to_string(),
-            binary: binary,
-            attachment: attachment,
+            binary,
+            attachment,
         }
     }
 
+    /// Text formats shrink well, while images, media and archives are compressed already
+    pub(crate) fn compressible(&self) -> bool {
+        let content_type = self.content_type.as_str();
+        !self.binary || content_type.starts_with("text/") || content_type.ends_with("+xml")
+            || content_type.ends_with("+json") || COMPRESSIBLE_TYPES.contains(&content_type)
+    }
+
     pub(crate) fn default_extension() -> MimeTypeProperties {
         MimeTypeProperties {
             content_type: "application/octet-stream".to_string(),
@@ -1041,6 +1060,50 @@ pub(crate) fn extract_extension(file_name: &str) -> Option<String> {
     None
 }
 
+/// A content type configured for a file extension, replacing the built-in one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeTypeOverride {
+    pub extension: String,
+    pub content_type: String,
+    pub binary: bool,
+    pub attachment: bool,
+}
+
+/// Parses "md=text/markdown" or "dat=application/x-data;attachment". Types other than text/*, +xml and +json
+/// count as binary unless the flag "text" says otherwise.
+pub fn parse_mime_type_override(mime_type: &str) -> Result<MimeTypeOverride, String> {
+    let mut parts = mime_type.split(';').map(|part| part.trim());
+    let (extension, content_type) = parts.next().unwrap_or("").split_once('=')
+        .filter(|(extension, content_type)| !extension.trim().is_empty() && content_type.contains('/'))
+        .ok_or(format!("Expected an extension and a content type like md=text/markdown, got {mime_type}"))?;
+    let content_type = content_type.trim().to_string();
+    let binary = !(content_type.starts_with("text/") || content_type.ends_with("+xml") || content_type.ends_with("+json"));
+    let mut mime_type_override = MimeTypeOverride {
+        extension: extension.trim().trim_start_matches('.').to_lowercase(),
+        content_type,
+        binary,
+        attachment: false,
+    };
+    for flag in parts.filter(|part| !part.is_empty()) {
+        match flag.to_ascii_lowercase().as_str() {
+            "text" => mime_type_override.binary = false,
+            "binary" => mime_type_override.binary = true,
+            "attachment" => mime_type_override.attachment = true,
+            _ => return Err(format!("Unknown MIME type flag {flag}, use text, binary or attachment"))
+        }
+    }
+    Ok(mime_type_override)
+}
+
+/// The configured content type of the extension, None when the built-in one applies
+pub(crate) fn find_mime_type_override(overrides: &[MimeTypeOverride], extension_option: Option<&str>) -> Option<MimeTypeProperties> {
+    let extension = extension_option?;
+    overrides.iter()
+        .rfind(|mime_type_override| mime_type_override.extension == extension)
+        .map(|mime_type_override| MimeTypeProperties::new(&mime_type_override.content_type, mime_type_override.binary,
+                                                          mime_type_override.attachment))
+}
+
 pub(crate) fn extract_mime_type(extension_option: Option<String>)
                                 -> MimeTypeProperties {
     match extension_option {
@@ -1061,6 +1124,20 @@ pub(crate) fn extract_mime_type(extension_option: Option<String>)
 mod tests {
     use super::*;
 
+    #[test]
+    fn when_mime_type_is_overridden_should_use_configured_type() {
+        let overrides = vec![parse_mime_type_override("md=text/markdown").unwrap(),
+                             parse_mime_type_override(".DAT=application/x-data;attachment").unwrap()];
+        let markdown = find_mime_type_override(&overrides, Some("md")).unwrap();
+        assert_eq!(markdown.content_type, "text/markdown");
+        assert!(!markdown.binary);
+        let data = find_mime_type_override(&overrides, Some("dat")).unwrap();
+        assert!(data.binary && data.attachment);
+        assert!(find_mime_type_override(&overrides, Some("png")).is_none());
+        assert!(parse_mime_type_override("md").is_err());
+        assert!(parse_mime_type_override("md=text/markdown;inline").is_err());
+    }
+
     #[test]
     fn when_extract_extension_should_find_png() {
         test_extension("flower.png", "png");
@@ -1081,6 +1158,15 @@ mod tests {
         test_mime_conversion("flower.jpeg", "image/jpeg");
     }
 
+    #[test]
+    fn when_compressible_should_accept_text_formats_only() {
+        assert!(extract_mime_type(extract_extension("skeleton.css")).compressible());
+        assert!(extract_mime_type(extract_extension("logo.svg")).compressible());
+        assert!(extract_mime_type(extract_extension("app.js")).compressible());
+        assert!(!extract_mime_type(extract_extension("flower.png")).compressible());
+        assert!(!extract_mime_type(extract_extension("archive.7z")).compressible());
+    }
+
     fn test_extension(file_name: &str, expected: &str) {
         let mimetype_option = extract_extension(file_name);
         assert!(mimetype_option.is_some(), "Result should return some");
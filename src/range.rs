@@ -0,0 +1,247 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::conditional::{strong_match, Validators};
+use crate::header_parser::HeaderMap;
+use crate::http_date::{parse_http_date, truncate_to_seconds};
+
+/// Above this number of ranges the Range header is ignored and the full content is sent
+const MAX_RANGES: usize = 64;
+
+/// Inclusive byte range of the selected representation
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) struct ByteRange {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+}
+
+impl ByteRange {
+    pub(crate) fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub(crate) fn content_range(&self, complete_length: u64) -> String {
+        format!("bytes {}-{}/{complete_length}", self.start, self.end)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum RangeResponse {
+    Full,
+    Partial(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+/// Decides whether a GET request gets the full content, some ranges of it or a 416 response
+pub(crate) fn evaluate_range(headers: &HeaderMap, length: u64, validators: &Validators) -> RangeResponse {
+    let range = match headers.get("Range") {
+        Some(range) => range,
+        None => return RangeResponse::Full
+    };
+    if let Some(if_range) = headers.get("If-Range") {
+        if !if_range_matches(if_range, validators) {
+            return RangeResponse::Full;
+        }
+    }
+    match parse_range(range, length) {
+        Some(ranges) if ranges.is_empty() => RangeResponse::Unsatisfiable,
+        Some(ranges) if ranges.len() <= MAX_RANGES => RangeResponse::Partial(coalesce_ranges(ranges)),
+        _ => RangeResponse::Full
+    }
+}
+
+/// Sorts the ranges and merges overlapping or adjacent ones, so that repeating a range
+/// can't make the response body larger than the content itself.
+fn coalesce_ranges(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut coalesced: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match coalesced.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => last.end = last.end.max(range.end),
+            _ => coalesced.push(range)
+        }
+    }
+    coalesced
+}
+
+/// If-Range only matches on an unchanged validator: entity tags need a strong match and dates an exact one.
+fn if_range_matches(if_range: &str, validators: &Validators) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return match &validators.etag {
+            Some(etag) => strong_match(if_range, etag),
+            None => false
+        };
+    }
+    match (parse_http_date(if_range), validators.last_modified) {
+        (Some(date), Some(last_modified)) => date == truncate_to_seconds(last_modified) && date > UNIX_EPOCH,
+        _ => false
+    }
+}
+
+/// Parses a bytes Range header value, returning the satisfiable ranges,
+/// or None when the header is syntactically invalid and has to be ignored.
+pub(crate) fn parse_range(range: &str, length: u64) -> Option<Vec<ByteRange>> {
+    let (unit, range_set) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in range_set.split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()) {
+        specs += 1;
+        let (first, last) = spec.split_once('-')?;
+        let range_option = match (parse_position(first), parse_position(last)) {
+            (Some(first), Some(last)) if first <= last =>
+                if first < length { Some(ByteRange { start: first, end: last.min(length - 1) }) } else { None },
+            (Some(first), None) if last.is_empty() =>
+                if first < length { Some(ByteRange { start: first, end: length - 1 }) } else { None },
+            (None, Some(suffix)) if first.is_empty() =>
+                if suffix > 0 && length > 0 { Some(ByteRange { start: length - suffix.min(length), end: length - 1 }) } else { None },
+            _ => return None
+        };
+        if let Some(range) = range_option {
+            ranges.push(range);
+        }
+    }
+    if specs == 0 {
+        return None;
+    }
+    Some(ranges)
+}
+
+fn parse_position(position: &str) -> Option<u64> {
+    if position.is_empty() || !position.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    position.parse::<u64>().ok()
+}
+
+pub(crate) fn multipart_boundary() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    format!("GIL_HTTP_{nanos:x}")
+}
+
+/// The delimiter and headers which precede each part of a multipart/byteranges body
+pub(crate) fn multipart_part_header(boundary: &str, content_type: &str, range: &ByteRange, length: u64) -> String {
+    format!("\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n", range.content_range(length))
+}
+
+pub(crate) fn multipart_end(boundary: &str) -> String {
+    format!("\r\n--{boundary}--\r\n")
+}
+
+/// The exact Content-Length of a multipart/byteranges body
+pub(crate) fn multipart_length(boundary: &str, content_type: &str, ranges: &[ByteRange], length: u64) -> u64 {
+    let parts_length: u64 = ranges.iter()
+        .map(|range| multipart_part_header(boundary, content_type, range, length).len() as u64 + range.len())
+        .sum();
+    parts_length + multipart_end(boundary).len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::args::ContentCoding;
+    use crate::header_parser::message_headers;
+
+    use super::*;
+
+    fn headers(header_str: &str) -> HeaderMap {
+        let (_, header_map) = message_headers(format!("{header_str}\r\n").as_bytes()).unwrap();
+        header_map
+    }
+
+    #[test]
+    fn when_parse_range_should_handle_all_forms() {
+        let ranges = parse_range("bytes=0-499, 500-, -200", 1000).unwrap();
+        assert_eq!(ranges, vec![
+            ByteRange { start: 0, end: 499 },
+            ByteRange { start: 500, end: 999 },
+            ByteRange { start: 800, end: 999 },
+        ]);
+    }
+
+    #[test]
+    fn when_parse_range_beyond_length_should_clamp_or_drop() {
+        assert_eq!(parse_range("bytes=900-2000", 1000).unwrap(), vec![ByteRange { start: 900, end: 999 }]);
+        assert_eq!(parse_range("bytes=-5000", 1000).unwrap(), vec![ByteRange { start: 0, end: 999 }]);
+        assert!(parse_range("bytes=1000-", 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn when_parse_range_invalid_should_be_ignored() {
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("lines=1-2", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("bytes=", 1000), None);
+    }
+
+    #[test]
+    fn when_evaluate_range_should_select_response() {
+        let validators = Validators::default();
+        assert_eq!(evaluate_range(&headers("Host: a\r\n"), 10, &validators), RangeResponse::Full);
+        assert_eq!(evaluate_range(&headers("Range: bytes=0-0\r\n"), 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 0 }]));
+        assert_eq!(evaluate_range(&headers("Range: bytes=20-\r\n"), 10, &validators), RangeResponse::Unsatisfiable);
+    }
+
+    #[test]
+    fn when_evaluate_range_with_overlapping_ranges_should_merge_them() {
+        let validators = Validators::default();
+        assert_eq!(evaluate_range(&headers("Range: bytes=0-,0-,0-\r\n"), 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 9 }]));
+        assert_eq!(evaluate_range(&headers("Range: bytes=6-7,0-1,2-3,1-2\r\n"), 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 3 }, ByteRange { start: 6, end: 7 }]));
+        assert_eq!(evaluate_range(&headers("Range: bytes=-3,0-4,5-6\r\n"), 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 9 }]));
+    }
+
+    #[test]
+    fn when_evaluate_range_with_if_range_should_validate_date() {
+        let validators = Validators {
+            etag: None,
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(784_111_777_500)),
+        };
+        let matching = headers("Range: bytes=0-0\r\nIf-Range: Sun, 06 Nov 1994 08:49:37 GMT\r\n");
+        assert_eq!(evaluate_range(&matching, 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 0 }]));
+        let outdated = headers("Range: bytes=0-0\r\nIf-Range: Sun, 06 Nov 1994 08:49:36 GMT\r\n");
+        assert_eq!(evaluate_range(&outdated, 10, &validators), RangeResponse::Full);
+    }
+
+    #[test]
+    fn when_evaluate_range_with_if_range_should_compare_etags_strongly() {
+        let validators = Validators { etag: Some("\"abc\"".to_string()), last_modified: None };
+        let matching = headers("Range: bytes=0-0\r\nIf-Range: \"abc\"\r\n");
+        assert_eq!(evaluate_range(&matching, 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 0 }]));
+        let weak = headers("Range: bytes=0-0\r\nIf-Range: W/\"abc\"\r\n");
+        assert_eq!(evaluate_range(&weak, 10, &validators), RangeResponse::Full);
+    }
+
+    #[test]
+    fn when_evaluate_range_of_precompressed_file_should_match_coding_etag() {
+        let validators = Validators { etag: Some("\"abc\"".to_string()), last_modified: None }
+            .for_representation(Some(ContentCoding::Br));
+        let matching = headers("Range: bytes=0-0\r\nIf-Range: \"abc-br\"\r\n");
+        assert_eq!(evaluate_range(&matching, 10, &validators),
+                   RangeResponse::Partial(vec![ByteRange { start: 0, end: 0 }]));
+        let identity = headers("Range: bytes=0-0\r\nIf-Range: \"abc\"\r\n");
+        assert_eq!(evaluate_range(&identity, 10, &validators), RangeResponse::Full);
+    }
+
+    #[test]
+    fn when_multipart_length_should_match_written_bytes() {
+        let ranges = vec![ByteRange { start: 0, end: 1 }, ByteRange { start: 5, end: 9 }];
+        let mut body = String::new();
+        for range in &ranges {
+            body += multipart_part_header("b", "text/plain", range, 10).as_str();
+            body += &"x".repeat(range.len() as usize);
+        }
+        body += multipart_end("b").as_str();
+        assert_eq!(multipart_length("b", "text/plain", &ranges, 10), body.len() as u64);
+    }
+}
@@ -0,0 +1,58 @@
+use std::io;
+use std::io::{BufRead, ErrorKind, Read};
+
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Reads the request head, i.e. the request line and all headers up to and including the
+/// empty line. Empty lines preceding the request line are skipped.
+/// Returns an empty vector if the connection was closed before anything was sent.
+pub(crate) fn read_request_head(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    loop {
+        // One byte more than allowed tells a head which is too large from one which fits exactly
+        let limit = (MAX_HEAD_SIZE + 1 - head.len()) as u64;
+        let read = reader.by_ref().take(limit).read_until(b'\n', &mut head)?;
+        if read == 0 {
+            return if head.is_empty() { Ok(head) } else {
+                Err(io::Error::new(ErrorKind::UnexpectedEof, "Connection closed inside request head"))
+            };
+        }
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Request head too large"));
+        }
+        if head == b"\r\n" || head == b"\n" {
+            head.clear();
+            continue;
+        }
+        if head.ends_with(b"\r\n\r\n") {
+            return Ok(head);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    #[test]
+    fn when_read_request_head_should_stop_at_empty_line() {
+        let mut reader = BufReader::new("\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\nbody".as_bytes());
+        let head = read_request_head(&mut reader).unwrap();
+        assert_eq!(head, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+    }
+
+    #[test]
+    fn when_read_request_head_truncated_should_err() {
+        let mut reader = BufReader::new("GET / HTTP/1.1\r\nHost: a\r\n".as_bytes());
+        assert!(read_request_head(&mut reader).is_err());
+    }
+
+    #[test]
+    fn when_read_request_head_line_is_too_long_should_stop_reading() {
+        let mut reader = BufReader::new(io::repeat(b'a'));
+        let error = read_request_head(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
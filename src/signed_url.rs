@@ -0,0 +1,124 @@
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use linked_hash_map::LinkedHashMap;
+use sha2::Sha256;
+
+use crate::string_operations::percent_encode;
+
+pub(crate) const SIGNATURE_PARAM: &str = "signature";
+const EXPIRES_PARAM: &str = "expires";
+const IP_PARAM: &str = "ip";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum SignedUrl {
+    Valid,
+    Expired,
+    /// A missing parameter, another path or client, or a tampered signature
+    Invalid,
+}
+
+/// The secret is the trimmed content of the file, so it can be generated with e.g. "openssl rand -hex 32"
+pub(crate) fn read_secret(path: &str) -> io::Result<Vec<u8>> {
+    Ok(fs::read_to_string(path)?.trim().as_bytes().to_vec())
+}
+
+fn url_mac(secret: &[u8], path: &str, expires: u64, client_ip: Option<IpAddr>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    let client_ip = client_ip.map(|ip| ip.to_string()).unwrap_or_default();
+    mac.update(format!("{path}\n{expires}\n{client_ip}").as_bytes());
+    mac
+}
+
+/// The path with the query parameters granting access to it until expires, a Unix timestamp,
+/// optionally only from the client IP
+pub(crate) fn sign_url(secret: &[u8], path: &str, expires: u64, client_ip: Option<IpAddr>) -> String {
+    let signature = url_mac(secret, path, expires, client_ip).finalize().into_bytes().iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    let ip_param = client_ip.map(|ip| format!("&{IP_PARAM}={ip}")).unwrap_or_default();
+    format!("{}?{EXPIRES_PARAM}={expires}{ip_param}&{SIGNATURE_PARAM}={signature}", percent_encode(path))
+}
+
+/// Checks the signature of the query parameters for the normalized path, comparing it in constant time
+pub(crate) fn verify_signed_url(secret: &[u8], path: &str, query_params: &LinkedHashMap<String, Vec<String>>,
+                                peer_ip: Option<IpAddr>, now: u64) -> SignedUrl {
+    let param = |name: &str| query_params.get(name).and_then(|values| values.first());
+    let Some(signature) = param(SIGNATURE_PARAM).and_then(|signature| decode_hex(signature)) else {
+        return SignedUrl::Invalid;
+    };
+    let Some(expires) = param(EXPIRES_PARAM).and_then(|expires| expires.parse::<u64>().ok()) else {
+        return SignedUrl::Invalid;
+    };
+    let client_ip = match param(IP_PARAM).map(|ip| ip.parse::<IpAddr>()) {
+        Some(Ok(ip)) => Some(ip),
+        Some(Err(_)) => return SignedUrl::Invalid,
+        None => None
+    };
+    if url_mac(secret, path, expires, client_ip).verify_slice(&signature).is_err() {
+        return SignedUrl::Invalid;
+    }
+    if client_ip.is_some() && client_ip != peer_ip.map(|ip| ip.to_canonical()) {
+        println!("Signed URL for {path} is bound to another client");
+        return SignedUrl::Invalid;
+    }
+    if now > expires {
+        return SignedUrl::Expired;
+    }
+    SignedUrl::Valid
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_parser::Uri;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"server-secret";
+
+    fn verify(url: &str, peer_ip: &str, now: u64) -> SignedUrl {
+        let uri = Uri::parse(url).unwrap();
+        verify_signed_url(SECRET, &uri.path, &uri.query_params, peer_ip.parse().ok(), now)
+    }
+
+    #[test]
+    fn when_sign_url_should_encode_path() {
+        let url = sign_url(SECRET, "/reports/q1 2024.pdf", 1700000000, None);
+        assert!(url.starts_with("/reports/q1%202024.pdf?expires=1700000000&signature="));
+        assert_eq!(verify(&url, "10.0.0.1", 1700000000), SignedUrl::Valid);
+    }
+
+    #[test]
+    fn when_verify_signed_url_should_reject_expired_and_tampered_links() {
+        let url = sign_url(SECRET, "/reports/q1.pdf", 1700000000, None);
+        assert_eq!(verify(&url, "10.0.0.1", 1700000001), SignedUrl::Expired);
+        assert_eq!(verify(&url.replace("q1.pdf", "q2.pdf"), "10.0.0.1", 1700000000), SignedUrl::Invalid);
+        assert_eq!(verify(&url.replace("1700000000", "1800000000"), "10.0.0.1", 1700000000), SignedUrl::Invalid);
+        assert_eq!(verify("/reports/q1.pdf?expires=1700000000", "10.0.0.1", 1700000000), SignedUrl::Invalid);
+        let other_secret = sign_url(b"other-secret", "/reports/q1.pdf", 1700000000, None);
+        assert_eq!(verify(&other_secret, "10.0.0.1", 1700000000), SignedUrl::Invalid);
+    }
+
+    #[test]
+    fn when_verify_signed_url_bound_to_ip_should_check_client() {
+        let url = sign_url(SECRET, "/reports/q1.pdf", 1700000000, "10.0.0.1".parse().ok());
+        assert!(url.contains("&ip=10.0.0.1&"));
+        assert_eq!(verify(&url, "10.0.0.1", 1700000000), SignedUrl::Valid);
+        assert_eq!(verify(&url, "::ffff:10.0.0.1", 1700000000), SignedUrl::Valid);
+        assert_eq!(verify(&url, "10.0.0.2", 1700000000), SignedUrl::Invalid);
+        assert_eq!(verify(&url.replace("&ip=10.0.0.1", ""), "10.0.0.2", 1700000000), SignedUrl::Invalid);
+    }
+}
@@ -1,15 +1,15 @@
 use fancy_regex::Regex;
 
-const DEFAULT_INDEX_FILE: &'static str = "index.html";
+const DEFAULT_INDEX_FILE: &str = "index.html";
 
 pub(crate) fn replace_slash(uri: String) -> String {
-    return extract_from_str(&Regex::new("(.*)/$").expect("Slash regex is not correct"),
-                            uri.to_string(), format!("${{1}}/{DEFAULT_INDEX_FILE}").to_string());
+    extract_from_str(&Regex::new("(.*)/$").expect("Slash regex is not correct"),
+                            uri.to_string(), format!("${{1}}/{DEFAULT_INDEX_FILE}").to_string())
 }
 
 pub(crate) fn extract_file_name(uri: String) -> String {
-    return extract_from_str(&Regex::new(".*/(.+)$").expect("File name regex is not correct"),
-                            uri.to_string(), "${1}".to_string());
+    extract_from_str(&Regex::new(".*/(.+)$").expect("File name regex is not correct"),
+                            uri.to_string(), "${1}".to_string())
 }
 
 pub(crate) fn remove_double_slash(uri: &str) -> String {
@@ -17,9 +17,54 @@ pub(crate) fn remove_double_slash(uri: &str) -> String {
     regex.replace_all(uri, "/").to_string()
 }
 
+/// Checks whether the path lies below the prefix, comparing whole segments, so "/api" covers "/api/x" but not "/apix"
+pub(crate) fn matches_path_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false
+    }
+}
+
+/// Escapes backslashes and double quotes for the inside of a quoted-string
+pub(crate) fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Decodes %XX escapes, failing on malformed escapes or when the result is not UTF-8
+pub(crate) fn percent_decode(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes everything but unreserved characters and slashes, so the path can be used in a URL
+pub(crate) fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{b:02X}")
+        })
+        .collect()
+}
+
 fn extract_from_str(regex: &Regex, uri: String, rep: String) -> String {
     let result = regex.replace(uri.as_str(), rep);
-    return result.to_string();
+    result.to_string()
 }
 
 #[cfg(test)]
@@ -58,6 +103,34 @@ mod tests {
                    String::from("test.pdf"));
     }
 
+    #[test]
+    fn when_percent_decode_should_decode_escapes() {
+        assert_eq!(percent_decode("/my%20file%C3%A9.txt"), Some("/my fileé.txt".to_string()));
+        assert_eq!(percent_decode("/%2e%2E/"), Some("/../".to_string()));
+    }
+
+    #[test]
+    fn when_percent_encode_should_round_trip() {
+        assert_eq!(percent_encode("/my fileé.txt?#"), "/my%20file%C3%A9.txt%3F%23");
+        assert_eq!(percent_decode(&percent_encode("/a b/c%d")), Some("/a b/c%d".to_string()));
+    }
+
+    #[test]
+    fn when_percent_decode_malformed_should_fail() {
+        assert_eq!(percent_decode("/file%2"), None);
+        assert_eq!(percent_decode("/file%zz"), None);
+        assert_eq!(percent_decode("/file%+f"), None);
+        assert_eq!(percent_decode("/file%ff"), None);
+    }
+
+    #[test]
+    fn when_matches_path_prefix_should_compare_segments() {
+        assert!(matches_path_prefix("/api", "/api"));
+        assert!(matches_path_prefix("/api/users", "/api/"));
+        assert!(matches_path_prefix("/anything", "/"));
+        assert!(!matches_path_prefix("/apix", "/api"));
+    }
+
     #[test]
     fn when_extract_pdf_name_should_extract_test1_pdf() {
         assert_eq!(extract_file_name(String::from("/test/test1/test.pdf")),
@@ -0,0 +1,172 @@
+use std::ffi::OsString;
+use std::ptr;
+
+use clap::Parser;
+
+use crate::args::{AuthMode, HttpServerArgs, Mode, RunCommand, UnknownHost};
+use crate::https_redirect::host_without_port;
+
+/// Where a request goes after looking at its host name and the server name of the TLS handshake
+pub(crate) enum Route<'a> {
+    Serve(&'a RunCommand),
+    /// The host belongs to no virtual host and unknown hosts are rejected, or it differs from the host
+    /// the TLS session was established for
+    Misdirected,
+    /// The Host header is no host name
+    BadRequest,
+}
+
+/// A host name like "example.com" or a wildcard like "*.example.com", in lower case
+pub fn parse_server_name(name: &str) -> Result<String, String> {
+    let name = name.trim().to_ascii_lowercase();
+    let domain = name.strip_prefix("*.").unwrap_or(&name);
+    let valid = !domain.is_empty()
+        && domain.split('.').all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    if !valid {
+        return Err(format!("Invalid server name {name}"));
+    }
+    Ok(name)
+}
+
+/// A wildcard stands for one or more labels in front of its domain, not for the domain itself
+fn matches_server_name(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+        None => pattern == host,
+    }
+}
+
+/// The host serving the name: an exact name wins over wildcards, a longer wildcard over a shorter one
+fn find_host<'a>(default: &'a RunCommand, virtual_hosts: &'a [RunCommand], host: &str) -> Option<&'a RunCommand> {
+    let hosts = || std::iter::once(default).chain(virtual_hosts.iter());
+    hosts().find(|run_args| run_args.server_names.iter().any(|name| name == host))
+        .or_else(|| hosts()
+            .filter_map(|run_args| run_args.server_names.iter()
+                .filter(|name| name.starts_with('*') && matches_server_name(name, host))
+                .map(|name| (name.len(), run_args))
+                .max_by_key(|(length, _)| *length))
+            .max_by_key(|(length, _)| *length)
+            .map(|(_, run_args)| run_args))
+}
+
+/// Chooses the host of a request by the host of its target or Host header, and the server name of the TLS
+/// handshake when it has none. Requests without either, which HTTP/1.0 allows, go to the default host.
+pub(crate) fn route<'a>(default: &'a RunCommand, virtual_hosts: &'a [RunCommand], host: Option<&str>,
+                        server_name: Option<&str>) -> Route<'a> {
+    let host = match host.map(host_without_port) {
+        Some(Some(host)) => Some(host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase()),
+        Some(None) => return Route::BadRequest,
+        None => None,
+    };
+    let by_host = host.as_deref().map(|host| find_host(default, virtual_hosts, host));
+    let by_server_name = server_name.map(|server_name| find_host(default, virtual_hosts, &server_name.to_ascii_lowercase()));
+    // The certificate was chosen for the server name, so another host may not be reached over the same session
+    if let (Some(by_host), Some(by_server_name)) = (by_host, by_server_name) {
+        let host_or_default = by_host.unwrap_or(default);
+        if !ptr::eq(host_or_default, by_server_name.unwrap_or(default)) {
+            return Route::Misdirected;
+        }
+    }
+    match by_host.or(by_server_name) {
+        Some(Some(run_args)) => Route::Serve(run_args),
+        Some(None) if default.unknown_host == UnknownHost::Reject => Route::Misdirected,
+        _ => Route::Serve(default),
+    }
+}
+
+/// Parses the arguments of every virtual host block, which are those of the run command with the settings
+/// of the block in place of the ones it replaces
+pub(crate) fn parse_virtual_hosts(default: &RunCommand, virtual_host_args: Vec<Vec<OsString>>) -> Result<Vec<RunCommand>, String> {
+    let mut virtual_hosts = vec![];
+    for (i, args) in virtual_host_args.into_iter().enumerate() {
+        let run_args = match HttpServerArgs::try_parse_from(args) {
+            Ok(HttpServerArgs { mode: Mode::Run(run_args) }) => *run_args,
+            Ok(_) => unreachable!("Virtual hosts are parsed as run commands"),
+            Err(e) => return Err(format!("Invalid virtual host {}: {}", i + 1, e.to_string().trim_end())),
+        };
+        // The client certificate is requested during the handshake, before the host is known
+        if matches!(run_args.auth_mode, AuthMode::Certificate(_)) && !matches!(default.auth_mode, AuthMode::Certificate(_)) {
+            return Err(format!("Virtual host {} can only use client certificates when the server uses them", run_args.server_names.join(",")));
+        }
+        let taken = std::iter::once(default).chain(virtual_hosts.iter())
+            .flat_map(|other: &RunCommand| other.server_names.iter())
+            .find(|name| run_args.server_names.contains(name));
+        if let Some(name) = taken {
+            return Err(format!("The server name {name} belongs to more than one host"));
+        }
+        virtual_hosts.push(run_args);
+    }
+    Ok(virtual_hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_command(args: &[&str]) -> RunCommand {
+        let args = ["http_server", "run", "--host", "127.0.0.1", "--port", "80"].iter().chain(args).chain(["none"].iter());
+        match HttpServerArgs::parse_from(args).mode {
+            Mode::Run(run_args) => *run_args,
+            _ => unreachable!()
+        }
+    }
+
+    fn root_folder<'a>(route: Route<'a>) -> Option<&'a str> {
+        match route {
+            Route::Serve(run_args) => Some(run_args.root_folder.as_str()),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn when_parse_server_name_should_accept_names_and_wildcards() {
+        assert_eq!(parse_server_name("Docs.Example.com").unwrap(), "docs.example.com");
+        assert_eq!(parse_server_name("*.example.com").unwrap(), "*.example.com");
+        assert!(parse_server_name("").is_err());
+        assert!(parse_server_name("docs..example.com").is_err());
+        assert!(parse_server_name("*example.com").is_err());
+        assert!(parse_server_name("docs.example.com:80").is_err());
+    }
+
+    #[test]
+    fn when_route_should_choose_host_by_name() {
+        let default = run_command(&["--root-folder", "default"]);
+        let virtual_hosts = [
+            run_command(&["--server-names", "docs.example.com,docs", "--root-folder", "docs"]),
+            run_command(&["--server-names", "*.example.com", "--root-folder", "wildcard"]),
+            run_command(&["--server-names", "*.eu.example.com", "--root-folder", "eu"]),
+        ];
+        let route = |host: Option<&str>| root_folder(super::route(&default, &virtual_hosts, host, None));
+        assert_eq!(route(Some("docs.example.com:8080")), Some("docs"));
+        assert_eq!(route(Some("DOCS")), Some("docs"));
+        assert_eq!(route(Some("wiki.example.com")), Some("wildcard"));
+        assert_eq!(route(Some("shop.eu.example.com")), Some("eu"));
+        assert_eq!(route(Some("example.com")), Some("default"));
+        assert_eq!(route(Some("[::1]:80")), Some("default"));
+        assert_eq!(route(None), Some("default"));
+        assert!(matches!(super::route(&default, &virtual_hosts, Some("evil.example/x"), None), Route::BadRequest));
+    }
+
+    #[test]
+    fn when_host_is_unknown_should_reject_only_when_configured() {
+        let default = run_command(&["--server-names", "example.com", "--unknown-host", "reject", "--root-folder", "default"]);
+        let virtual_hosts = [run_command(&["--server-names", "docs.example.com", "--root-folder", "docs"])];
+        assert_eq!(root_folder(route(&default, &virtual_hosts, Some("example.com"), None)), Some("default"));
+        assert_eq!(root_folder(route(&default, &virtual_hosts, None, None)), Some("default"));
+        assert!(matches!(route(&default, &virtual_hosts, Some("other.example"), None), Route::Misdirected));
+    }
+
+    #[test]
+    fn when_host_differs_from_server_name_should_be_misdirected() {
+        let default = run_command(&["--root-folder", "default"]);
+        let virtual_hosts = [
+            run_command(&["--server-names", "docs.example.com", "--root-folder", "docs"]),
+            run_command(&["--server-names", "wiki.example.com", "--root-folder", "wiki"]),
+        ];
+        assert_eq!(root_folder(route(&default, &virtual_hosts, None, Some("wiki.example.com"))), Some("wiki"));
+        assert_eq!(root_folder(route(&default, &virtual_hosts, Some("docs.example.com"), Some("DOCS.example.com"))), Some("docs"));
+        assert_eq!(root_folder(route(&default, &virtual_hosts, Some("a.test"), Some("b.test"))), Some("default"));
+        assert!(matches!(route(&default, &virtual_hosts, Some("wiki.example.com"), Some("docs.example.com")), Route::Misdirected));
+        assert!(matches!(route(&default, &virtual_hosts, Some("wiki.example.com"), Some("other.test")), Route::Misdirected));
+    }
+}